@@ -25,7 +25,7 @@
 //!     "state": "Pending",
 //!     "timestamp": 1704067200000
 //!   },
-//!   "body": "/monitor/pic/shot"
+//!   "body": "/monitor/pic/shot?resolution=1440*720"
 //! }]
 //! ```
 
@@ -38,7 +38,66 @@ mod shot_pic;
 async fn main() {
     println!("Starting Bapao Screenshot Service...");
     
-    let mut btp_listener = bapao_app_protocal::AppListener::new();
+    // 配置只在启动时读取一次，路径可以用 BAPAO_CONFIG 指定
+    let config = match bapao_app_protocal::BapaoConfig::load_default() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Failed to load config: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let mut btp_listener = bapao_app_protocal::AppListener::with_backend(
+        bapao_app_protocal::GiteeBackend::with_config(config.clone()),
+    );
+
+    // 配置了 secret_key 时，只处理加密的请求；配置有误时不能退化成明文传输
+    match bapao_app_protocal::KeyPair::from_config(&config) {
+        Ok(Some(keys)) => {
+            println!("End-to-end encryption enabled, public key: {}", keys.public_key().to_base64());
+            btp_listener.set_encryption(keys);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("Failed to read secret_key: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // 配置了 trusted_clients 时，只处理这些客户端签名的请求；配置有误时不能退化成不验证
+    match bapao_app_protocal::TrustList::from_config(&config) {
+        Ok(Some(trust_list)) => {
+            println!("Request signatures are verified against trusted_clients");
+            btp_listener.set_trust_list(trust_list);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("Failed to read trusted_clients: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // 配置了 policies 时，按规则限制客户端可以调用的接口
+    match bapao_app_protocal::Policy::from_config(&config) {
+        Ok(Some(policy)) => {
+            println!("Route policies loaded from the config");
+            btp_listener.set_policy(policy);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("Failed to read policies: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    // 记录已处理的请求，重启之后被重放的请求也不会再次执行
+    match bapao_app_protocal::ReplayGuard::open("bapao.replay.json") {
+        Ok(guard) => btp_listener.set_replay_guard(guard),
+        Err(err) => {
+            eprintln!("Failed to read bapao.replay.json: {}", err);
+            std::process::exit(1);
+        }
+    }
 
     // Register the screenshot endpoint
     btp_listener.add("/monitor/pic/shot", shot_pic);
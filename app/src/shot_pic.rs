@@ -7,7 +7,7 @@ extern crate image_base64;
 
 use std::fs;
 use std::process;
-use bapao_app_protocal::TransUnitType;
+use bapao_app_protocal::{RequestContext, TransUnitType};
 
 /// Captures a screenshot and returns it as binary data.
 /// 
@@ -15,6 +15,11 @@ use bapao_app_protocal::TransUnitType;
 /// Currently configured to read a static image file, but includes commented
 /// code for dynamic screenshot capture using system commands.
 /// 
+/// # Parameters
+/// 
+/// * `_ctx` - The request context; the dynamic capture code reads the
+///   `resolution` query parameter from it (e.g. `/monitor/pic/shot?resolution=1440*720`)
+/// 
 /// # Returns
 /// 
 /// `TransUnitType::File(Vec<u8>)` - Binary image data of the screenshot
@@ -22,7 +27,7 @@ use bapao_app_protocal::TransUnitType;
 /// # Examples
 /// 
 /// ```rust
-/// use bapao_app_protocal::TransUnitType;
+/// use bapao_app_protocal::{RequestContext, TransUnitType};
 /// use shot_pic::shot_pic;
 /// 
 /// let screenshot = shot_pic();
@@ -30,8 +35,11 @@ use bapao_app_protocal::TransUnitType;
 ///     TransUnitType::File(data) => {
 ///         println!("Screenshot captured: {} bytes", data.len());
 ///     },
-///     TransUnitType::String(error) => {
-///         println!("Screenshot failed: {}", error);
+///     TransUnitType::String(text) => {
+///         println!("Unexpected text response: {}", text);
+///     },
+///     TransUnitType::Error(err) => {
+///         println!("Screenshot failed: {}", err);
 ///     }
 /// }
 /// ```
@@ -45,9 +53,11 @@ use bapao_app_protocal::TransUnitType;
 /// - Linux: `scrot`, `gnome-screenshot`, `import` (ImageMagick)
 /// - macOS: `screencapture`
 /// - Windows: PowerShell with System.Drawing
-pub fn shot_pic() -> TransUnitType {
+pub fn shot_pic(_ctx: RequestContext) -> TransUnitType {
+    // let resolution = _ctx.param("resolution").unwrap_or("1440*720");
+    //
     // if let Ok(mut child) = process::Command::new("fswebcam")
-    //     .args(["-r", "1440*720", "/home/pi/image.jpg"])
+    //     .args(["-r", resolution, "/home/pi/image.jpg"])
     //     .spawn()
     // {
     //     child.wait().unwrap();
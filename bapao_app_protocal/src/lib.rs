@@ -1,22 +1,53 @@
-use bapao_trans_protocal;
-pub use bapao_trans_protocal::trans_content::TransUnitType;
-use std::{collections::HashMap, thread, time::Duration};
+mod policy;
+
+use bapao_trans_protocal::{self, scheduler::PollScheduler, BtpListener};
+pub use bapao_trans_protocal::auth::TrustList;
+pub use bapao_trans_protocal::backend::{GiteeBackend, MailboxBackend};
+pub use bapao_trans_protocal::clock::{Clock, ManualClock, SystemClock};
+pub use bapao_trans_protocal::config::{BapaoConfig, ConfigError};
+pub use bapao_trans_protocal::crypto::KeyPair;
+pub use bapao_trans_protocal::error::BtpError;
+pub use bapao_trans_protocal::replay::ReplayGuard;
+pub use bapao_trans_protocal::retry::{RateBudget, RetryPolicy};
+pub use bapao_trans_protocal::scheduler::{HourWindow, PollSchedule, QuietHours};
+pub use bapao_trans_protocal::trans_content::{ErrorCode, TransError, TransUnitType};
+pub use bapao_trans_protocal::trans_unit::RequestContext;
+pub use policy::{AllowedHours, Policy, PolicyRule, RateLimit};
+use futures::FutureExt;
+use std::{
+    any::Any,
+    collections::HashMap,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::Semaphore;
 
 /// High-level application listener for handling requests through the Bapao communication system.
 /// 
 /// `AppListener` provides a simple interface for registering route handlers and processing
-/// incoming requests from external clients through Gitee repositories.
+/// incoming requests from external clients through Gitee repositories, or through
+/// any other `MailboxBackend`.
+/// 
+/// Handlers are stored boxed, so plain `fn` items and closures capturing
+/// configuration, clients or shared state can be registered side by side.
+/// Handlers can be synchronous (`add`) or `async` (`add_async`); the pending
+/// requests of one poll cycle run concurrently, up to the limit set with
+/// `set_concurrency`. How often the mailbox is polled is controlled by the
+/// `PollSchedule` set with `set_schedule`.
 /// 
 /// # Type Parameters
 /// 
-/// * `T` - A function type that returns `TransUnitType`. All registered handlers must have this signature.
+/// * `B` - The mailbox backend requests are read from, `GiteeBackend` by default.
 /// 
 /// # Examples
 /// 
-/// ```rust
-/// use bapao_app_protocal::{AppListener, TransUnitType};
+/// ```rust,no_run
+/// use bapao_app_protocal::{AppListener, RequestContext, TransUnitType};
 /// 
-/// fn status_handler() -> TransUnitType {
+/// fn status_handler(_ctx: RequestContext) -> TransUnitType {
 ///     TransUnitType::String("System is running".to_string())
 /// }
 /// 
@@ -27,17 +58,72 @@ use std::{collections::HashMap, thread, time::Duration};
 ///     listener.listen().await;
 /// }
 /// ```
-pub struct AppListener<T>
-where
-    T: Fn() -> TransUnitType,
-{
-    listener: HashMap<&'static str, T>,
+pub struct AppListener<B = GiteeBackend> {
+    listener: HashMap<&'static str, Handler>,
+    trans_listener: BtpListener<B>,
+    concurrency: usize,
+    scheduler: PollScheduler,
+    policy: Option<Policy>,
 }
 
-impl<T> AppListener<T>
-where
-    T: Fn() -> TransUnitType,
-{
+type HandlerFuture = Pin<Box<dyn Future<Output = TransUnitType> + Send>>;
+
+/// A registered route handler.
+#[derive(Clone)]
+enum Handler {
+    /// Blocking handler, run on tokio's blocking thread pool
+    Sync(Arc<dyn Fn(RequestContext) -> TransUnitType + Send + Sync>),
+    /// `async` handler, run as a tokio task
+    Async(Arc<dyn Fn(RequestContext) -> HandlerFuture + Send + Sync>),
+}
+
+impl Handler {
+    /// Runs the handler, turning a panic into an `Internal` error response.
+    async fn call(self, ctx: RequestContext) -> TransUnitType {
+        let result = match self {
+            Handler::Sync(callback) => {
+                let task = tokio::task::spawn_blocking(move || {
+                    panic::catch_unwind(AssertUnwindSafe(|| callback(ctx)))
+                });
+
+                task.await.unwrap_or_else(|err| Err(join_error_payload(err)))
+            }
+            Handler::Async(callback) => {
+                AssertUnwindSafe(async move { callback(ctx).await })
+                    .catch_unwind()
+                    .await
+            }
+        };
+
+        result.unwrap_or_else(|payload| {
+            TransUnitType::Error(TransError::new(
+                ErrorCode::Internal,
+                format!("handler panicked: {}", panic_message(&*payload)),
+            ))
+        })
+    }
+}
+
+fn join_error_payload(err: tokio::task::JoinError) -> Box<dyn Any + Send> {
+    if err.is_panic() {
+        err.into_panic()
+    } else {
+        Box::new(err.to_string())
+    }
+}
+
+/// 取出 panic 时携带的信息，一般是 `&str` 或 `String`
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+impl AppListener<GiteeBackend> {
     /// Creates a new `AppListener` with an empty route table.
     /// 
     /// # Returns
@@ -52,60 +138,350 @@ where
     /// let mut listener = AppListener::new();
     /// ```
     pub fn new() -> Self {
+        AppListener::with_backend(GiteeBackend::new())
+    }
+}
+
+impl Default for AppListener<GiteeBackend> {
+    fn default() -> Self {
+        AppListener::new()
+    }
+}
+
+impl<B> AppListener<B>
+where
+    B: MailboxBackend,
+{
+    /// Creates a new `AppListener` that reads requests through `backend`.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `backend` - The mailbox backend to poll for requests
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_app_protocal::{AppListener, GiteeBackend, TransUnitType};
+    /// 
+    /// let mut listener = AppListener::with_backend(GiteeBackend::new());
+    /// listener.add("/ping", |_ctx| TransUnitType::String("pong".to_string()));
+    /// ```
+    pub fn with_backend(backend: B) -> Self {
         AppListener {
             listener: HashMap::new(),
+            trans_listener: BtpListener::with_backend(backend),
+            concurrency: 4,
+            scheduler: PollScheduler::new(PollSchedule::default()),
+            policy: None,
+        }
+    }
+
+    /// Sets the poll schedule used by `listen`.
+    /// 
+    /// By default the mailbox is polled every 10 seconds while requests keep
+    /// arriving, backing off up to once a minute when it stays idle.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `schedule` - Min/max poll intervals, backoff factor and optional quiet hours
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_app_protocal::{AppListener, PollSchedule};
+    /// use std::time::Duration;
+    /// 
+    /// let mut listener = AppListener::new();
+    /// listener.set_schedule(PollSchedule {
+    ///     min_interval: Duration::from_secs(3),
+    ///     max_interval: Duration::from_secs(300),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn set_schedule(&mut self, schedule: PollSchedule) {
+        let clock = self.trans_listener.clock().clone();
+
+        self.scheduler = PollScheduler::new(schedule).with_clock(clock);
+    }
+
+    /// Sets the clock used for expiry, response timestamps and poll timing.
+    /// 
+    /// See `BtpListener::set_clock`. `listen` also waits between polls and
+    /// checks quiet hours with this clock, so with a `ManualClock` a whole day
+    /// of polling runs in an instant. The policy (see `set_policy`) checks
+    /// its hours and rate limits with it as well. The backend has its own clock for
+    /// retries, e.g. `GiteeBackend::with_clock`.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        let schedule = self.scheduler.schedule().clone();
+
+        self.scheduler = PollScheduler::new(schedule).with_clock(clock.clone());
+
+        if let Some(policy) = &mut self.policy {
+            policy.set_clock(clock.clone());
         }
+
+        self.trans_listener.set_clock(clock);
+    }
+
+    /// Sets how long requests stay valid.
+    /// 
+    /// See `BtpListener::set_expiry`; defaults to 30 minutes.
+    pub fn set_expiry(&mut self, expiry: Duration) {
+        self.trans_listener.set_expiry(expiry);
+    }
+
+    /// Sets how many requests of one poll cycle may be handled at the same time.
+    /// 
+    /// Defaults to 4. A limit of 1 handles requests one after another.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `limit` - Maximum number of handlers running concurrently (at least 1)
+    pub fn set_concurrency(&mut self, limit: usize) {
+        self.concurrency = limit.max(1);
+    }
+
+    /// Sets the size above which file responses are split into chunks.
+    /// 
+    /// See `BtpListener::set_chunk_size`; defaults to 512 KiB.
+    pub fn set_chunk_size(&mut self, bytes: usize) {
+        self.trans_listener.set_chunk_size(bytes);
+    }
+
+    /// Enables end-to-end encryption of requests and responses.
+    /// 
+    /// See `BtpListener::set_encryption`. Handlers keep receiving the decrypted
+    /// route and payload; plaintext requests are rejected once this is set.
+    pub fn set_encryption(&mut self, keys: KeyPair) {
+        self.trans_listener.set_encryption(keys);
+    }
+
+    /// Only handles requests signed by a client in `trust_list`.
+    /// 
+    /// See `BtpListener::set_trust_list`. Other requests are answered with an
+    /// `Unauthorized` error without reaching a handler; handlers find the key
+    /// id of the verified client in `RequestContext::client_id`.
+    pub fn set_trust_list(&mut self, trust_list: TrustList) {
+        self.trans_listener.set_trust_list(trust_list);
+    }
+
+    /// Sets the record of dispatched requests used to reject replays.
+    /// 
+    /// See `BtpListener::set_replay_guard`. Replayed requests are answered
+    /// with a `Replayed` error and never reach a handler.
+    pub fn set_replay_guard(&mut self, guard: ReplayGuard) {
+        self.trans_listener.set_replay_guard(guard);
+    }
+
+    /// Checks every request against `policy` before its handler runs.
+    /// 
+    /// Requests the policy does not allow are answered with a `Forbidden` or
+    /// `RateLimited` error. Without a policy every route is open to every
+    /// client. Rules match on the key id of the verified client, so the policy
+    /// is meant to be combined with `set_trust_list`. The policy uses the
+    /// listener's clock, see `set_clock`.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_app_protocal::{AppListener, Policy, PolicyRule};
+    /// 
+    /// let mut policy = Policy::new();
+    /// policy.add(PolicyRule { route: "/monitor/**".to_string(), ..Default::default() });
+    /// 
+    /// let mut listener = AppListener::new();
+    /// listener.set_policy(policy);
+    /// ```
+    pub fn set_policy(&mut self, mut policy: Policy) {
+        policy.set_clock(self.trans_listener.clock().clone());
+        self.policy = Some(policy);
     }
 
     /// Registers a callback function for a specific route path.
     /// 
-    /// When a request is received with a body matching the specified key,
-    /// the associated callback function will be executed.
+    /// When a request is received whose route matches the specified key,
+    /// the associated callback function will be executed with the request's
+    /// `RequestContext` (id, timestamp, query parameters, payload and headers).
+    /// 
+    /// A handler reports a failure by returning `TransUnitType::Error`; a
+    /// handler that panics is answered with an `Internal` error, and requests
+    /// for unregistered routes with a `NotFound` error.
     /// 
     /// # Parameters
     /// 
     /// * `key` - The route path to handle (e.g., "/api/status", "/monitor/pic/shot")
-    /// * `callback` - Function that returns a `TransUnitType` response
+    /// * `callback` - Function or closure that returns a `TransUnitType` response
+    /// 
+    /// The callback may block (run a command, read a device): it is executed
+    /// on tokio's blocking thread pool so it does not stall other requests.
     /// 
     /// # Examples
     /// 
     /// ```rust
-    /// use bapao_app_protocal::{AppListener, TransUnitType};
+    /// use bapao_app_protocal::{AppListener, RequestContext, TransUnitType};
+    /// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
     /// 
-    /// fn echo_handler() -> TransUnitType {
-    ///     TransUnitType::String("Echo response".to_string())
+    /// fn echo_handler(ctx: RequestContext) -> TransUnitType {
+    ///     TransUnitType::String(ctx.param("text").unwrap_or("Echo response").to_string())
     /// }
     /// 
+    /// let greeting = String::from("Hello");
+    /// let counter = Arc::new(AtomicUsize::new(0));
+    /// 
     /// let mut listener = AppListener::new();
     /// listener.add("/echo", echo_handler);
+    /// listener.add("/greet", move |ctx| {
+    ///     let name = ctx.header("client").unwrap_or("stranger");
+    ///     TransUnitType::String(format!("{}, {}", greeting, name))
+    /// });
+    /// listener.add("/count", move |_ctx| {
+    ///     let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+    ///     TransUnitType::String(count.to_string())
+    /// });
+    /// ```
+    pub fn add<F>(&mut self, key: &'static str, callback: F)
+    where
+        F: Fn(RequestContext) -> TransUnitType + Send + Sync + 'static,
+    {
+        self.listener.insert(key, Handler::Sync(Arc::new(callback)));
+    }
+
+    /// Registers an `async` callback for a specific route path.
+    /// 
+    /// Works like `add`, but the callback returns a future that is awaited on
+    /// the tokio runtime, so it can await other I/O without holding a thread.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `key` - The route path to handle
+    /// * `callback` - Function or closure returning a future of a `TransUnitType`
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_app_protocal::{AppListener, RequestContext, TransUnitType};
+    /// 
+    /// async fn slow_status(_ctx: RequestContext) -> TransUnitType {
+    ///     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    ///     TransUnitType::String("OK".to_string())
+    /// }
+    /// 
+    /// let mut listener = AppListener::new();
+    /// listener.add_async("/status", slow_status);
     /// ```
-    pub fn add(&mut self, key: &'static str, callback: T) {
-        self.listener.insert(key, callback);
+    pub fn add_async<F, Fut>(&mut self, key: &'static str, callback: F)
+    where
+        F: Fn(RequestContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = TransUnitType> + Send + 'static,
+    {
+        let callback = move |ctx| -> HandlerFuture { Box::pin(callback(ctx)) };
+
+        self.listener.insert(key, Handler::Async(Arc::new(callback)));
+    }
+
+    /// Runs a single poll cycle.
+    /// 
+    /// Sends the responses of the previous cycle, fetches the pending requests
+    /// and runs their handlers. The responses produced here are sent by the
+    /// next cycle.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of requests handled in this cycle.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns the `BtpError` when the mailbox cannot be read or written; the
+    /// responses of the previous cycle are kept and sent by the next one.
+    pub async fn poll(&mut self) -> Result<usize, BtpError> {
+        let trans_listener = &mut self.trans_listener;
+
+        let incoming_data = trans_listener.accept().await?;
+        let handled = incoming_data.len();
+
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+
+        // 同一批请求并发执行，由 semaphore 控制同时执行的数量
+        let tasks: Vec<_> = incoming_data
+            .into_iter()
+            .map(|unit| {
+                let callback = self.listener.get(unit.route()).cloned();
+                let ctx = unit.context();
+                let semaphore = semaphore.clone();
+
+                // 先检查权限，不允许的请求不会执行处理函数
+                let allowed = match &mut self.policy {
+                    Some(policy) => policy.check(&ctx),
+                    None => Ok(()),
+                };
+
+                let task = tokio::spawn(async move {
+                    if let Err(err) = allowed {
+                        return TransUnitType::Error(err);
+                    }
+
+                    let callback = match callback {
+                        Some(callback) => callback,
+                        None => {
+                            return TransUnitType::Error(TransError::new(
+                                ErrorCode::NotFound,
+                                format!("no handler registered for route {}", ctx.route),
+                            ))
+                        }
+                    };
+
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+
+                    callback.call(ctx).await
+                });
+
+                (unit, task)
+            })
+            .collect();
+
+        for (unit, task) in tasks {
+            let res_content = task.await.unwrap_or_else(|err| {
+                TransUnitType::Error(TransError::new(ErrorCode::Internal, err.to_string()))
+            });
+
+            let res_unit = unit.set(res_content);
+
+            trans_listener.stash(res_unit);
+        }
+
+        Ok(handled)
     }
 
     /// Starts the listener and begins processing incoming requests.
     /// 
-    /// This function runs indefinitely, polling the Gitee repository every 10 seconds
-    /// for new requests. When requests are found, they are routed to the appropriate
-    /// registered handlers based on their body content.
+    /// This function runs indefinitely, polling the mailbox backend according
+    /// to the configured `PollSchedule`. When requests are found, they are routed
+    /// to the appropriate registered handlers based on their route.
     /// 
     /// # Behavior
     /// 
-    /// - Polls Gitee repository every 10 seconds
-    /// - Processes all pending requests in each cycle
+    /// - Polls every 10 seconds while requests arrive, backing off to once a
+    ///   minute when idle (see `set_schedule`)
+    /// - Waits without blocking the tokio worker between polls
+    /// - Processes all pending requests in each cycle, concurrently up to the
+    ///   configured limit
     /// - Automatically sends responses back to the repository
-    /// - Handles errors gracefully and continues operation
+    /// - Reports mailbox errors and keeps polling; errors that will not go away
+    ///   by retrying (see `BtpError::is_transient`) are reported as such
+    /// - Slows down once the backend reports that its API budget runs low (see
+    ///   `MailboxBackend::rate_budget`), and waits out a rate limit the server
+    ///   asked to respect
     /// 
     /// # Examples
     /// 
-    /// ```rust
+    /// ```rust,no_run
     /// use bapao_app_protocal::{AppListener, TransUnitType};
     /// 
     /// #[tokio::main]
     /// async fn main() {
     ///     let mut listener = AppListener::new();
     ///     
-    ///     listener.add("/status", || {
+    ///     listener.add("/status", |_ctx| {
     ///         TransUnitType::String("OK".to_string())
     ///     });
     ///     
@@ -113,25 +489,36 @@ where
     ///     listener.listen().await;
     /// }
     /// ```
-    pub async fn listen(&self) {
-        let mut trans_listener = bapao_trans_protocal::BtpListener::new();
-
+    pub async fn listen(&mut self) {
         loop {
-            thread::sleep(Duration::new(10, 0));
+            let mut retry_after = None;
 
-            let mut incoming_data = trans_listener.accept().await;
+            let handled = match self.poll().await {
+                Ok(handled) => handled,
+                Err(err) if err.is_transient() => {
+                    eprintln!("读取 mailbox 出错，稍后重试：{}", err);
+                    retry_after = err.retry_after();
+                    0
+                }
+                Err(err) => {
+                    eprintln!("读取 mailbox 出错，需要检查配置或权限：{}", err);
+                    0
+                }
+            };
 
-            incoming_data.iter_mut().for_each(|unit| {
-                let req_content = unit.get();
+            let clock = self.trans_listener.clock().clone();
+            let mut delay = self.scheduler.next_delay(handled > 0);
 
-                let callback = &self.listener.get(&req_content[..]).unwrap();
+            // 剩余的 API 调用次数不多时放慢轮询，被限流时至少等到限流结束
+            if let Some(budget) = self.trans_listener.backend().rate_budget() {
+                delay = delay.max(budget.pacing_at(clock.now()));
+            }
 
-                let res_content = callback();
+            if let Some(retry_after) = retry_after {
+                delay = delay.max(retry_after);
+            }
 
-                let res_unit = unit.set(res_content);
-
-                trans_listener.stash(res_unit);
-            });
+            clock.sleep(delay).await;
         }
     }
 }
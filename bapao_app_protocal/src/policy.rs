@@ -0,0 +1,514 @@
+use bapao_trans_protocal::clock::{self, Clock};
+use bapao_trans_protocal::config::BapaoConfig;
+use bapao_trans_protocal::scheduler::HourWindow;
+use bapao_trans_protocal::trans_content::{ErrorCode, TransError};
+use bapao_trans_protocal::trans_unit::RequestContext;
+use chrono::{DateTime, NaiveTime, Utc};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    sync::Arc,
+    time::Duration,
+};
+
+/// Name of the config section holding the policy rules.
+pub const POLICIES: &str = "policies";
+
+/// Limits how often one client may call the routes of a rule.
+///
+/// # Fields
+///
+/// * `requests` - Number of requests allowed within the window
+/// * `per_secs` - Length of the sliding window in seconds
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: usize,
+    pub per_secs: u64,
+}
+
+/// Daily window during which the routes of a rule may be called, see
+/// `HourWindow`.
+pub type AllowedHours = HourWindow;
+
+/// Who may call the routes matching `route`, and when.
+///
+/// # Fields
+///
+/// * `route` - Route pattern: `*` matches one path segment, `**` any number
+///   of segments (e.g. `/monitor/**`, `/shell/*`)
+/// * `clients` - Key ids allowed to call the routes, `"*"` for every verified
+///   client; `None` allows everyone, including unsigned requests
+/// * `hours` - Daily window (local time) during which the routes may be called
+/// * `rate_limit` - Maximum number of calls per client
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_app_protocal::{PolicyRule, RateLimit};
+///
+/// let rule = PolicyRule {
+///     route: "/shell/*".to_string(),
+///     clients: Some(vec!["admin".to_string()]),
+///     rate_limit: Some(RateLimit { requests: 5, per_secs: 60 }),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PolicyRule {
+    pub route: String,
+    pub clients: Option<Vec<String>>,
+    pub hours: Option<AllowedHours>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+/// 配置文件中的规则，时间段写作 "09:00-18:00"
+#[derive(Deserialize, Debug)]
+struct RuleConfig {
+    route: String,
+    #[serde(default)]
+    clients: Option<Vec<String>>,
+    #[serde(default)]
+    hours: Option<String>,
+    #[serde(default)]
+    rate_limit: Option<RateLimit>,
+}
+
+impl RuleConfig {
+    fn into_rule(self) -> Result<PolicyRule, Box<dyn Error + Send + Sync>> {
+        let hours = match self.hours {
+            Some(hours) => Some(parse_hours(&hours)?),
+            None => None,
+        };
+
+        Ok(PolicyRule {
+            route: self.route,
+            clients: self.clients,
+            hours,
+            rate_limit: self.rate_limit,
+        })
+    }
+}
+
+fn parse_hours(value: &str) -> Result<AllowedHours, Box<dyn Error + Send + Sync>> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| format!("hours must look like 09:00-18:00, got {}", value))?;
+
+    Ok(AllowedHours::new(
+        NaiveTime::parse_from_str(start.trim(), "%H:%M")?,
+        NaiveTime::parse_from_str(end.trim(), "%H:%M")?,
+    ))
+}
+
+/// 按路径段匹配，`*` 匹配一段，`**` 匹配任意多段
+fn matches(pattern: &[&str], route: &[&str]) -> bool {
+    match (pattern.split_first(), route.split_first()) {
+        (Some((&"**", rest)), _) => {
+            matches(rest, route) || (!route.is_empty() && matches(pattern, &route[1..]))
+        }
+        (Some((segment, rest)), Some((part, route_rest))) => {
+            (*segment == "*" || segment == part) && matches(rest, route_rest)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn segments(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect()
+}
+
+/// Authorization rules checked by `AppListener` before a handler runs.
+///
+/// The first rule whose `route` pattern matches the request decides; a route
+/// no rule matches is forbidden. A request is answered with `Forbidden` when
+/// its client is not listed in the rule or it arrives outside the rule's
+/// `hours`, and with `RateLimited` when the client exceeded the rule's
+/// `rate_limit`. Clients are identified by the key id of their signature,
+/// see `AppListener::set_trust_list`.
+///
+/// Configured in `bapao.config.json`:
+///
+/// ```json
+/// {
+///   "policies": [
+///     { "route": "/monitor/**" },
+///     { "route": "/shell/*", "clients": ["admin"], "hours": "09:00-18:00",
+///       "rate_limit": { "requests": 5, "per_secs": 60 } }
+///   ]
+/// }
+/// ```
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_app_protocal::{Policy, PolicyRule};
+///
+/// let mut policy = Policy::new();
+/// policy.add(PolicyRule { route: "/monitor/**".to_string(), ..Default::default() });
+/// policy.add(PolicyRule {
+///     route: "/shell/*".to_string(),
+///     clients: Some(vec!["admin".to_string()]),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Debug)]
+pub struct Policy {
+    rules: Vec<PolicyRule>,
+    /// 每条规则下每个客户端最近的请求时间，用于限流
+    calls: HashMap<(usize, String), VecDeque<DateTime<Utc>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            rules: vec![],
+            calls: HashMap::new(),
+            clock: clock::system(),
+        }
+    }
+}
+
+impl Policy {
+    /// Creates a policy without rules, which forbids every route.
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    /// Reads the rules from the `policies` section of `config`, `None` when
+    /// the section is missing.
+    ///
+    /// # Errors
+    ///
+    /// Fails when a rule is malformed, e.g. `hours` is not `HH:MM-HH:MM`.
+    pub fn from_config(config: &BapaoConfig) -> Result<Option<Self>, Box<dyn Error + Send + Sync>> {
+        let rules: Vec<RuleConfig> = match config.section(POLICIES)? {
+            Some(rules) => rules,
+            None => return Ok(None),
+        };
+
+        let mut policy = Policy::new();
+
+        for rule in rules {
+            policy.add(rule.into_rule()?);
+        }
+
+        Ok(Some(policy))
+    }
+
+    /// Uses `clock` for `hours` and `rate_limit` instead of the system clock.
+    ///
+    /// `AppListener::set_policy` replaces it with the listener's clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Appends `rule`; rules are checked in the order they were added.
+    pub fn add(&mut self, rule: PolicyRule) {
+        self.rules.push(rule);
+    }
+
+    /// Checks whether the request described by `ctx` may be handled now, and
+    /// counts it towards the rate limit of its rule.
+    ///
+    /// # Errors
+    ///
+    /// The `Forbidden` or `RateLimited` error to answer the request with.
+    pub fn check(&mut self, ctx: &RequestContext) -> Result<(), TransError> {
+        let route = segments(&ctx.route);
+
+        let (index, rule) = self
+            .rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| matches(&segments(&rule.route), &route))
+            .ok_or_else(|| {
+                TransError::new(
+                    ErrorCode::Forbidden,
+                    format!("no policy allows route {}", ctx.route),
+                )
+            })?;
+
+        if let Some(clients) = &rule.clients {
+            let allowed = match &ctx.client_id {
+                Some(client_id) => clients
+                    .iter()
+                    .any(|client| client == "*" || client == client_id),
+                None => false,
+            };
+
+            if !allowed {
+                return Err(TransError::new(
+                    ErrorCode::Forbidden,
+                    format!(
+                        "client {} may not call {}",
+                        ctx.client_id.as_deref().unwrap_or("(unsigned)"),
+                        ctx.route
+                    ),
+                ));
+            }
+        }
+
+        if let Some(hours) = &rule.hours {
+            if !hours.contains(self.clock.local_now().time()) {
+                return Err(TransError::new(
+                    ErrorCode::Forbidden,
+                    format!(
+                        "{} may only be called between {} and {}",
+                        ctx.route,
+                        hours.start.format("%H:%M"),
+                        hours.end.format("%H:%M")
+                    ),
+                ));
+            }
+        }
+
+        if let Some(limit) = rule.rate_limit {
+            let now = self.clock.now();
+            let window = chrono::Duration::from_std(Duration::from_secs(limit.per_secs))
+                .unwrap_or_else(|_| chrono::Duration::max_value());
+
+            let calls = self
+                .calls
+                .entry((index, ctx.client_id.clone().unwrap_or_default()))
+                .or_default();
+
+            // 只保留窗口内的请求
+            while calls
+                .front()
+                .is_some_and(|call| now - *call >= window)
+            {
+                calls.pop_front();
+            }
+
+            if calls.len() >= limit.requests {
+                return Err(TransError::new(
+                    ErrorCode::RateLimited,
+                    format!(
+                        "at most {} requests per {}s are allowed for {}",
+                        limit.requests, limit.per_secs, ctx.route
+                    ),
+                ));
+            }
+
+            calls.push_back(now);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bapao_trans_protocal::clock::ManualClock;
+    use chrono::{Local, TimeZone};
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms(hour, minute, 0)
+    }
+
+    /// 本地时间 2024-03-01 的 `hour` 点
+    fn clock_at(hour: u32) -> ManualClock {
+        ManualClock::new(Local.ymd(2024, 3, 1).and_hms(hour, 0, 0).with_timezone(&Utc))
+    }
+
+    fn request(route: &str, client_id: Option<&str>) -> RequestContext {
+        RequestContext {
+            id: "req_001".to_string(),
+            timestamp: 0,
+            content_type: None,
+            route: route.to_string(),
+            query: HashMap::new(),
+            payload: None,
+            headers: HashMap::new(),
+            client_id: client_id.map(str::to_string),
+        }
+    }
+
+    fn rule(route: &str) -> PolicyRule {
+        PolicyRule {
+            route: route.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn code(result: Result<(), TransError>) -> Option<ErrorCode> {
+        result.err().map(|err| err.code)
+    }
+
+    #[test]
+    fn routes_match_by_segment() {
+        let matches = |pattern: &str, route: &str| matches(&segments(pattern), &segments(route));
+
+        assert!(matches("/monitor/pic/shot", "/monitor/pic/shot"));
+        assert!(matches("/shell/*", "/shell/ls"));
+        assert!(!matches("/shell/*", "/shell"));
+        assert!(!matches("/shell/*", "/shell/ls/-l"));
+        assert!(matches("/monitor/**", "/monitor"));
+        assert!(matches("/monitor/**", "/monitor/pic/shot"));
+        assert!(matches("/**/shot", "/monitor/pic/shot"));
+        assert!(!matches("/monitor/**", "/shell/ls"));
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let mut policy = Policy::new();
+        policy.add(PolicyRule {
+            clients: Some(vec!["admin".to_string()]),
+            ..rule("/shell/*")
+        });
+        policy.add(rule("/**"));
+
+        assert_eq!(code(policy.check(&request("/shell/ls", Some("admin")))), None);
+        assert_eq!(
+            code(policy.check(&request("/shell/ls", Some("guest")))),
+            Some(ErrorCode::Forbidden)
+        );
+        assert_eq!(code(policy.check(&request("/monitor/pic/shot", None))), None);
+    }
+
+    #[test]
+    fn unmatched_route_is_forbidden() {
+        let mut policy = Policy::new();
+        policy.add(rule("/monitor/**"));
+
+        assert_eq!(
+            code(policy.check(&request("/shell/ls", None))),
+            Some(ErrorCode::Forbidden)
+        );
+    }
+
+    #[test]
+    fn clients_require_a_verified_signature() {
+        let mut policy = Policy::new();
+        policy.add(PolicyRule {
+            clients: Some(vec!["*".to_string()]),
+            ..rule("/**")
+        });
+
+        assert_eq!(code(policy.check(&request("/ping", Some("laptop")))), None);
+        assert_eq!(
+            code(policy.check(&request("/ping", None))),
+            Some(ErrorCode::Forbidden)
+        );
+    }
+
+    #[test]
+    fn hours_wrap_around_midnight() {
+        let night = AllowedHours::new(time(22, 0), time(6, 0));
+
+        assert!(night.contains(time(22, 0)));
+        assert!(night.contains(time(23, 59)));
+        assert!(night.contains(time(0, 0)));
+        assert!(night.contains(time(5, 59)));
+        assert!(!night.contains(time(6, 0)));
+        assert!(!night.contains(time(12, 0)));
+
+        let office = AllowedHours::new(time(9, 0), time(18, 0));
+
+        assert!(office.contains(time(9, 0)));
+        assert!(!office.contains(time(18, 0)));
+        assert!(!office.contains(time(3, 0)));
+    }
+
+    #[test]
+    fn calls_outside_the_hours_are_forbidden() {
+        let clock = clock_at(23);
+
+        let mut policy = Policy::new().with_clock(Arc::new(clock.clone()));
+        policy.add(PolicyRule {
+            hours: Some(parse_hours("22:00-06:00").unwrap()),
+            ..rule("/backup")
+        });
+
+        assert_eq!(code(policy.check(&request("/backup", None))), None);
+
+        // 第二天 07:00
+        clock.advance(Duration::from_secs(8 * 60 * 60));
+        assert_eq!(
+            code(policy.check(&request("/backup", None))),
+            Some(ErrorCode::Forbidden)
+        );
+    }
+
+    #[test]
+    fn rate_limit_is_counted_per_client_and_rule() {
+        let clock = clock_at(12);
+
+        let mut policy = Policy::new().with_clock(Arc::new(clock.clone()));
+        policy.add(PolicyRule {
+            rate_limit: Some(RateLimit {
+                requests: 2,
+                per_secs: 60,
+            }),
+            ..rule("/shell/*")
+        });
+        policy.add(PolicyRule {
+            rate_limit: Some(RateLimit {
+                requests: 1,
+                per_secs: 60,
+            }),
+            ..rule("/monitor/**")
+        });
+
+        let admin = request("/shell/ls", Some("admin"));
+
+        assert_eq!(code(policy.check(&admin)), None);
+        assert_eq!(code(policy.check(&admin)), None);
+        assert_eq!(code(policy.check(&admin)), Some(ErrorCode::RateLimited));
+
+        // 其他客户端和其他规则各自计数
+        assert_eq!(code(policy.check(&request("/shell/ls", Some("laptop")))), None);
+        assert_eq!(code(policy.check(&request("/monitor/cpu", Some("admin")))), None);
+
+        // 窗口滑过之后又可以调用
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(code(policy.check(&admin)), None);
+    }
+
+    #[test]
+    fn rules_are_read_from_the_config() {
+        let config = BapaoConfig::parse(
+            r#"{
+                "access_token": "token",
+                "user_name": "bapao",
+                "repo": "mailbox",
+                "file_path": "io",
+                "policies": [
+                    { "route": "/monitor/**" },
+                    { "route": "/shell/*", "clients": ["admin"], "hours": "09:00-18:00",
+                      "rate_limit": { "requests": 5, "per_secs": 60 } }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let policy = Policy::from_config(&config).unwrap().unwrap();
+
+        assert_eq!(policy.rules[0], rule("/monitor/**"));
+        assert_eq!(
+            policy.rules[1],
+            PolicyRule {
+                clients: Some(vec!["admin".to_string()]),
+                hours: Some(AllowedHours::new(time(9, 0), time(18, 0))),
+                rate_limit: Some(RateLimit {
+                    requests: 5,
+                    per_secs: 60,
+                }),
+                ..rule("/shell/*")
+            }
+        );
+
+        assert!(parse_hours("9-18").is_err());
+    }
+}
@@ -0,0 +1,595 @@
+use bapao_app_protocal::{
+    AppListener, BtpError, ErrorCode, GiteeBackend, KeyPair, MailboxBackend, ManualClock,
+    RetryPolicy, TransUnitType,
+};
+use bapao_client::{BtpClient, CryptoError, ResponseTimeout, State};
+use bapao_trans_protocal::backend;
+use bapao_test_utils::{Fault, MockGitee, MAILBOX};
+use chrono::Utc;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// 测试中重试间隔很短，避免拖慢测试
+fn fast_retry() -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 3,
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(100),
+        ..Default::default()
+    }
+}
+
+fn backend(gitee: &MockGitee) -> GiteeBackend {
+    GiteeBackend::with_config(gitee.config()).with_retry_policy(fast_retry())
+}
+
+fn listener(gitee: &MockGitee) -> AppListener<GiteeBackend> {
+    with_routes(backend(gitee))
+}
+
+fn with_routes(backend: GiteeBackend) -> AppListener<GiteeBackend> {
+    let mut listener = AppListener::with_backend(backend);
+
+    listener.add("/echo", |ctx| {
+        TransUnitType::String(ctx.param("text").unwrap_or_default().to_string())
+    });
+    listener.add("/shot", |_ctx| {
+        TransUnitType::File(vec![0xff, 0xd8, 0xff, 0xe0])
+    });
+
+    listener
+}
+
+/// 第一轮接收并处理请求，第二轮把响应写回 mailbox
+async fn serve(listener: &mut AppListener<GiteeBackend>) {
+    assert_eq!(listener.poll().await.unwrap(), 1);
+    assert_eq!(listener.poll().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn string_response_round_trip() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/echo?text=hello", None).await.unwrap();
+    assert_eq!(client.fetch_response(&id).await.unwrap(), None);
+
+    serve(&mut listener).await;
+
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::String("hello".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn file_response_round_trip() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/shot", None).await.unwrap();
+
+    serve(&mut listener).await;
+
+    // 文件作为单独的 blob 上传，mailbox 中只有文件名
+    assert_eq!(gitee.files().len(), 2);
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::File(vec![0xff, 0xd8, 0xff, 0xe0]))
+    );
+
+    assert_eq!(client.purge().await.unwrap(), 1);
+    assert_eq!(gitee.files(), vec![MAILBOX.to_string()]);
+}
+
+#[tokio::test]
+async fn unknown_route_is_answered_with_an_error() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/missing", None).await.unwrap();
+
+    serve(&mut listener).await;
+
+    assert!(matches!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::Error(_))
+    ));
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/echo?text=retried", None).await.unwrap();
+
+    gitee.inject(Fault {
+        method: Some("GET".to_string()),
+        path: Some(MAILBOX.to_string()),
+        times: 2,
+        ..Fault::new(503)
+    });
+
+    serve(&mut listener).await;
+
+    let failed = gitee
+        .requests()
+        .iter()
+        .filter(|req| req.status == 503)
+        .count();
+    assert_eq!(failed, 2);
+
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::String("retried".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn persistent_failure_is_reported_after_the_last_attempt() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+
+    gitee.inject(Fault {
+        times: 3,
+        ..Fault::new(502)
+    });
+
+    let err = listener.poll().await.unwrap_err();
+
+    assert!(err.is_transient());
+    assert_eq!(gitee.requests().len(), 3);
+
+    // 故障消失之后恢复正常
+    assert_eq!(listener.poll().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn blob_stored_before_a_timeout_is_not_uploaded_again() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/shot", None).await.unwrap();
+
+    // 文件已经保存，但响应超时，重试时文件已存在
+    gitee.inject(Fault {
+        method: Some("POST".to_string()),
+        after_handling: true,
+        ..Fault::new(504)
+    });
+
+    serve(&mut listener).await;
+
+    let posts: Vec<u16> = gitee
+        .requests()
+        .iter()
+        .filter(|req| req.method == "POST")
+        .map(|req| req.status)
+        .collect();
+    assert_eq!(posts, vec![504, 400]);
+
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::File(vec![0xff, 0xd8, 0xff, 0xe0]))
+    );
+}
+
+#[tokio::test]
+async fn atomic_commit_writes_blob_and_mailbox_together() {
+    let gitee = MockGitee::start().await;
+    let mut listener = with_routes(backend(&gitee).with_atomic_commits());
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/shot", None).await.unwrap();
+
+    serve(&mut listener).await;
+
+    // 文件和 mailbox 在同一个提交中，没有单独通过 contents API 上传
+    assert!(!gitee
+        .requests()
+        .iter()
+        .any(|req| req.method == "POST" && req.path.is_some()));
+    assert_eq!(gitee.files().len(), 2);
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::File(vec![0xff, 0xd8, 0xff, 0xe0]))
+    );
+}
+
+#[tokio::test]
+async fn atomic_commit_is_retried_when_the_branch_moved() {
+    let gitee = MockGitee::start().await;
+    let mut listener = with_routes(backend(&gitee).with_atomic_commits());
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/shot", None).await.unwrap();
+
+    // 更新分支时已经有了新的提交
+    gitee.inject(Fault {
+        method: Some("PATCH".to_string()),
+        ..Fault::new(422)
+    });
+
+    serve(&mut listener).await;
+
+    let patches: Vec<u16> = gitee
+        .requests()
+        .iter()
+        .filter(|req| req.method == "PATCH")
+        .map(|req| req.status)
+        .collect();
+    assert_eq!(patches, vec![422, 200]);
+
+    // 失败的提交没有在仓库中留下文件
+    assert_eq!(gitee.files().len(), 2);
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::File(vec![0xff, 0xd8, 0xff, 0xe0]))
+    );
+}
+
+#[tokio::test]
+async fn atomic_commit_conflicts_when_the_mailbox_changed() {
+    let gitee = MockGitee::start().await;
+    let backend = backend(&gitee).with_atomic_commits();
+
+    let (content, sha) = backend.read_mailbox().await.unwrap();
+
+    gitee.set_file(MAILBOX, "[ ]");
+
+    let mut blobs = HashMap::new();
+    blobs.insert("67e55044-10b1-426f-9247-bb680e5fe0c8".to_string(), vec![1, 2, 3]);
+
+    let err = BtpError::from(backend.commit(&mut blobs, &content, &sha).await.unwrap_err());
+
+    assert!(matches!(err, BtpError::Conflict(_)));
+    assert_eq!(gitee.files(), vec![MAILBOX.to_string()]);
+    assert_eq!(gitee.file(MAILBOX).unwrap(), b"[ ]".to_vec());
+}
+
+#[tokio::test]
+async fn stale_mailbox_is_read_again() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let id = client.send("/echo?text=merged", None).await.unwrap();
+
+    // 监听端写入时 mailbox 已被别人修改
+    gitee.inject(Fault {
+        method: Some("PUT".to_string()),
+        path: Some(MAILBOX.to_string()),
+        ..Fault::new(409)
+    });
+
+    serve(&mut listener).await;
+
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::String("merged".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn write_with_stale_sha_conflicts() {
+    let gitee = MockGitee::start().await;
+    let backend = backend(&gitee);
+
+    let (content, sha) = backend.read_mailbox().await.unwrap();
+
+    // 读取之后 mailbox 被别人修改过，读到的 sha 已经过期
+    gitee.set_file(MAILBOX, "[ ]");
+
+    let err = BtpError::from(backend.write_mailbox(&content, &sha).await.unwrap_err());
+
+    assert!(matches!(err, BtpError::Conflict(_)));
+}
+
+#[tokio::test]
+async fn invalid_token_is_not_retried() {
+    let gitee = MockGitee::start().await;
+
+    let mut config = gitee.config();
+    config.access_token = String::from("wrong-token");
+
+    let mut listener = AppListener::with_backend(
+        GiteeBackend::with_config(config).with_retry_policy(fast_retry()),
+    );
+
+    let err = listener.poll().await.unwrap_err();
+
+    assert!(matches!(err, BtpError::Auth(_)));
+    assert!(!err.is_transient());
+    assert_eq!(gitee.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn exhausted_rate_limit_slows_down_polling() {
+    let gitee = MockGitee::start().await;
+    let backend = backend(&gitee);
+    let mut listener = AppListener::with_backend(backend.clone());
+
+    gitee.set_rate_limit(10, Duration::from_secs(3600));
+
+    for _ in 0..10 {
+        listener.poll().await.unwrap();
+    }
+
+    // 剩余次数低于 20% 之后开始放慢轮询
+    let budget = backend.rate_budget().unwrap();
+    assert_eq!(budget.remaining, 0);
+    assert!(budget.is_low());
+    assert!(budget.pacing() > Duration::from_secs(60));
+
+    // 服务端要求等待的时间超过 max_delay，不再重试，交给轮询循环处理
+    let err = listener.poll().await.unwrap_err();
+
+    assert!(matches!(err, BtpError::RateLimited { .. }));
+    assert!(err.retry_after().unwrap() > Duration::from_secs(60));
+    assert_eq!(gitee.requests().len(), 11);
+}
+
+#[tokio::test]
+async fn plaintext_response_to_an_encrypting_client_is_rejected() {
+    let gitee = MockGitee::start().await;
+    let agent_keys = KeyPair::generate();
+
+    let mut listener = listener(&gitee);
+    listener.set_encryption(agent_keys.clone());
+
+    let client = BtpClient::with_backend(backend(&gitee))
+        .with_encryption(KeyPair::generate(), agent_keys.public_key());
+
+    let id = client.send("/echo?text=secret", None).await.unwrap();
+    serve(&mut listener).await;
+
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::String("secret".to_string()))
+    );
+
+    // 有 mailbox 写权限的人把响应换成明文
+    backend::update_mailbox(&backend(&gitee), |mut content| {
+        let response = content.iter_mut().find(|item| item.head.id == id).unwrap();
+        response.head.cipher = None;
+        response.body = "forged".to_string();
+
+        Ok((Some(content), ()))
+    })
+    .await
+    .unwrap();
+
+    let err = client.fetch_response(&id).await.unwrap_err();
+    assert!(err.downcast_ref::<CryptoError>().is_some(), "{}", err);
+}
+
+#[tokio::test]
+async fn undecryptable_request_is_rejected_in_plaintext() {
+    let gitee = MockGitee::start().await;
+
+    let mut listener = listener(&gitee);
+    listener.set_encryption(KeyPair::generate());
+
+    // 客户端用错了监听端的公钥，监听端不知道回复的公钥
+    let client = BtpClient::with_backend(backend(&gitee))
+        .with_encryption(KeyPair::generate(), KeyPair::generate().public_key());
+
+    let id = client.send("/echo?text=secret", None).await.unwrap();
+
+    // 请求没有交给处理函数，直接以错误响应
+    assert_eq!(listener.poll().await.unwrap(), 0);
+    assert_eq!(listener.poll().await.unwrap(), 0);
+
+    let (content, _) = backend(&gitee).read_mailbox().await.unwrap();
+    assert!(content[0].head.cipher.is_none());
+
+    match client.fetch_response(&id).await.unwrap() {
+        Some(TransUnitType::Error(err)) => assert_eq!(err.code, ErrorCode::BadRequest),
+        other => panic!("unexpected response {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn replayed_request_is_rejected_encrypted() {
+    let gitee = MockGitee::start().await;
+    let agent_keys = KeyPair::generate();
+
+    let mut listener = listener(&gitee);
+    listener.set_encryption(agent_keys.clone());
+
+    let client = BtpClient::with_backend(backend(&gitee))
+        .with_encryption(KeyPair::generate(), agent_keys.public_key());
+
+    let id = client.send("/echo?text=secret", None).await.unwrap();
+    let (content, _) = backend(&gitee).read_mailbox().await.unwrap();
+    let request = content.into_iter().find(|item| item.head.id == id).unwrap();
+
+    serve(&mut listener).await;
+
+    // 有 mailbox 写权限的人把截获的请求再放回去
+    backend::update_mailbox(&backend(&gitee), |mut content| {
+        content.retain(|item| item.head.id != id);
+        content.push(request.clone());
+
+        Ok((Some(content), ()))
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(listener.poll().await.unwrap(), 0);
+    assert_eq!(listener.poll().await.unwrap(), 0);
+
+    let (content, _) = backend(&gitee).read_mailbox().await.unwrap();
+    assert!(content[0].head.cipher.is_some());
+
+    match client.fetch_response(&id).await.unwrap() {
+        Some(TransUnitType::Error(err)) => assert_eq!(err.code, ErrorCode::Replayed),
+        other => panic!("unexpected response {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn purge_only_deletes_generated_blobs() {
+    let gitee = MockGitee::start().await;
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    gitee.set_file("README.md", "# mailbox");
+
+    let id = client.send("/shot", None).await.unwrap();
+
+    // 伪造的文件响应指向仓库中的其他文件
+    backend::update_mailbox(&backend(&gitee), |mut content| {
+        let response = content.iter_mut().find(|item| item.head.id == id).unwrap();
+        response.head.state = State::Done;
+        response.head.content_type = Some("file".to_string());
+        response.body = "README.md".to_string();
+
+        Ok((Some(content), ()))
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(client.purge().await.unwrap(), 1);
+    assert_eq!(gitee.file("README.md"), Some(b"# mailbox".to_vec()));
+}
+
+#[tokio::test]
+async fn await_response_waits_on_the_client_clock() {
+    let gitee = MockGitee::start().await;
+    let clock = ManualClock::new(Utc::now());
+
+    let client = BtpClient::with_backend(backend(&gitee))
+        .with_poll_interval(Duration::from_secs(20))
+        .with_clock(Arc::new(clock.clone()));
+
+    let id = client.send("/echo?text=late", None).await.unwrap();
+
+    // 没有监听端，等待在模拟时间中完成，最后一次只等到超时为止
+    let err = client
+        .await_response(&id, Duration::from_secs(50))
+        .await
+        .unwrap_err();
+
+    assert!(err.downcast_ref::<ResponseTimeout>().is_some(), "{}", err);
+    assert_eq!(
+        clock.slept(),
+        vec![
+            Duration::from_secs(20),
+            Duration::from_secs(20),
+            Duration::from_secs(10),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn panicking_handlers_are_answered_with_an_internal_error() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    listener.add("/sync-panic", |_ctx| panic!("sync handler failed"));
+    listener.add_async("/async-panic", |_ctx| async {
+        panic!("async handler failed");
+    });
+
+    let sync_id = client.send("/sync-panic", None).await.unwrap();
+    let async_id = client.send("/async-panic", None).await.unwrap();
+
+    // panic 不会影响同一轮的其它请求，也不会让 poll 失败
+    assert_eq!(listener.poll().await.unwrap(), 2);
+    assert_eq!(listener.poll().await.unwrap(), 0);
+
+    for (id, message) in [
+        (sync_id, "sync handler failed"),
+        (async_id, "async handler failed"),
+    ] {
+        match client.fetch_response(&id).await.unwrap() {
+            Some(TransUnitType::Error(err)) => {
+                assert_eq!(err.code, ErrorCode::Internal);
+                assert!(err.message.contains(message), "{}", err.message);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn async_handlers_are_awaited() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    listener.add_async("/slow", |ctx| async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        TransUnitType::String(format!("{} done", ctx.route))
+    });
+
+    let id = client.send("/slow", None).await.unwrap();
+
+    serve(&mut listener).await;
+
+    assert_eq!(
+        client.fetch_response(&id).await.unwrap(),
+        Some(TransUnitType::String("/slow done".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn concurrent_handlers_are_limited() {
+    let gitee = MockGitee::start().await;
+    let mut listener = listener(&gitee);
+    let client = BtpClient::with_backend(backend(&gitee));
+
+    let running = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    listener.set_concurrency(2);
+    listener.add_async("/busy", {
+        let running = running.clone();
+        let peak = peak.clone();
+
+        move |_ctx| {
+            let running = running.clone();
+            let peak = peak.clone();
+
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+
+                TransUnitType::String("done".to_string())
+            }
+        }
+    });
+
+    let mut ids = Vec::new();
+    for _ in 0..6 {
+        ids.push(client.send("/busy", None).await.unwrap());
+    }
+
+    assert_eq!(listener.poll().await.unwrap(), 6);
+    assert_eq!(listener.poll().await.unwrap(), 0);
+
+    // 6 个请求同时到达，但最多只有 2 个处理函数在执行
+    assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+    for id in ids {
+        assert_eq!(
+            client.fetch_response(&id).await.unwrap(),
+            Some(TransUnitType::String("done".to_string()))
+        );
+    }
+}
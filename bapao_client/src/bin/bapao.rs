@@ -0,0 +1,340 @@
+//! # bapao
+//!
+//! Command-line tool for operators on the external side of the Bapao
+//! communication system. It reads and writes the mailbox configured in
+//! `bapao.config.json`, so nobody has to edit the `io` file by hand.
+//!
+//! ## Usage
+//!
+//! ```text
+//! bapao send <route> [body] [--header <key=value>]...
+//!                                             write a Pending request, print its id
+//! bapao wait <id> [--timeout <secs>] [--out <path>]
+//!                                             wait for the response to a request
+//! bapao ls                                    list mailbox entries with their age
+//! bapao cancel <id>                           cancel a request that is still pending
+//! bapao get-file <blob> [path]                save a file response blob to disk
+//! bapao purge                                 remove finished entries and their blobs
+//! bapao keygen [key-id]                       generate keys for encryption and signing
+//! ```
+//!
+//! Every command accepts `--sharded` to use the one-file-per-request mailbox
+//! layout (`ShardedBackend`) instead of the single `io` file. It has to match
+//! the layout the listener uses.
+//!
+//! The config is read from `--config <path>`, `$BAPAO_CONFIG` or
+//! `bapao.config.json` in the current directory; every field can also be set
+//! as a `BAPAO_*` environment variable, e.g. `BAPAO_ACCESS_TOKEN`.
+//!
+//! When the config contains `secret_key` (from `bapao keygen`) and
+//! the listener's `agent_public_key`, requests are encrypted end to end and
+//! responses are decrypted transparently. When it contains `key_id` and
+//! `signing_key`, every request is signed; the listener needs the matching
+//! `verify_key` in its `trusted_clients`.
+
+use bapao_client::{
+    BapaoConfig, BtpClient, GiteeBackend, KeyPair, MailboxBackend, PublicKey, ShardedBackend,
+    SigningKey, TransUnitType,
+};
+use chrono::Utc;
+use serde_json::Value;
+use std::{collections::HashMap, env, error::Error, fs, process, time::Duration};
+
+const USAGE: &str = "\
+Usage:
+  bapao send <route> [body] [--header <key=value>]...
+  bapao wait <id> [--timeout <secs>] [--out <path>]
+  bapao ls
+  bapao cancel <id>
+  bapao get-file <blob> [path]
+  bapao purge
+  bapao keygen [key-id]
+
+Options:
+  --sharded          use the one-file-per-request mailbox layout
+  --config <path>    read the config from <path> instead of bapao.config.json";
+
+#[tokio::main]
+async fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let sharded = args.iter().any(|arg| arg == "--sharded");
+    args.retain(|arg| arg != "--sharded");
+
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(index) if index + 1 < args.len() => {
+            let path = args.remove(index + 1);
+            args.remove(index);
+            Some(path)
+        }
+        Some(_) => usage(),
+        None => None,
+    };
+
+    let result = if args.first().map(|arg| &arg[..]) == Some("keygen") {
+        keygen(&args[1..])
+    } else {
+        // 配置只读取一次，所有请求共用
+        let config = match config_path {
+            Some(path) => BapaoConfig::load(path),
+            None => BapaoConfig::load_default(),
+        };
+
+        match config {
+            Ok(config) if sharded => {
+                let backend = ShardedBackend::new(GiteeBackend::with_config(config.clone()));
+                run(BtpClient::with_backend(backend), &config, &args).await
+            }
+            Ok(config) => {
+                let backend = GiteeBackend::with_config(config.clone());
+                run(BtpClient::with_backend(backend), &config, &args).await
+            }
+            Err(err) => Err(Box::from(err)),
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+async fn run<B: MailboxBackend>(
+    client: BtpClient<B>,
+    config: &BapaoConfig,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let client = &with_config_keys(client, config)?;
+
+    match args.first().map(|arg| &arg[..]) {
+        Some("send") => send(client, &args[1..]).await,
+        Some("wait") => wait(client, &args[1..]).await,
+        Some("ls") => ls(client).await,
+        Some("cancel") => cancel(client, &args[1..]).await,
+        Some("get-file") => get_file(client, &args[1..]).await,
+        Some("purge") => purge(client).await,
+        _ => usage(),
+    }
+}
+
+/// 配置了 secret_key 和 agent_public_key 时，请求加密发送；配置了 signing_key 时，请求签名发送
+fn with_config_keys<B: MailboxBackend>(
+    client: BtpClient<B>,
+    config: &BapaoConfig,
+) -> Result<BtpClient<B>, Box<dyn Error + Send + Sync>> {
+    let client = match SigningKey::from_config(config)? {
+        Some(key) => client.with_signing_key(key),
+        None => client,
+    };
+
+    match (
+        KeyPair::from_config(config)?,
+        PublicKey::from_config(config)?,
+    ) {
+        (Some(keys), Some(agent_key)) => Ok(client.with_encryption(keys, agent_key)),
+        (None, None) => Ok(client),
+        _ => Err(Box::from(
+            "secret_key and agent_public_key must be configured together",
+        )),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+async fn send<B: MailboxBackend>(
+    client: &BtpClient<B>,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut route = None;
+    let mut body = None;
+    let mut headers = HashMap::new();
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--header" | "-H" => {
+                let header = args.next().unwrap_or_else(|| usage());
+                let (key, value) = header.split_once('=').unwrap_or_else(|| usage());
+                headers.insert(key.to_string(), value.to_string());
+            }
+            _ if route.is_none() => route = Some(arg),
+            _ if body.is_none() => body = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let route = route.unwrap_or_else(|| usage());
+
+    // body 能按 JSON 解析就按 JSON 发送，否则当作普通字符串
+    let payload =
+        body.map(|body| serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.clone())));
+
+    let id = client.send_with_headers(route, payload, headers).await?;
+
+    println!("{}", id);
+
+    Ok(())
+}
+
+async fn wait<B: MailboxBackend>(
+    client: &BtpClient<B>,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut id = None;
+    let mut timeout = Duration::from_secs(300);
+    let mut out = None;
+
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--timeout" => {
+                let secs = args.next().unwrap_or_else(|| usage());
+                timeout = Duration::from_secs(secs.parse()?);
+            }
+            "--out" => out = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            _ if id.is_none() => id = Some(arg.clone()),
+            _ => usage(),
+        }
+    }
+
+    let id = id.unwrap_or_else(|| usage());
+
+    match client.await_response(&id, timeout).await? {
+        TransUnitType::String(text) => match out {
+            Some(path) => fs::write(path, text)?,
+            None => println!("{}", text),
+        },
+        TransUnitType::File(data) => {
+            // 文件响应没有指定路径时，用请求 id 作为文件名
+            let path = out.unwrap_or(id);
+            fs::write(&path, &data)?;
+            println!("saved {} bytes to {}", data.len(), path);
+        }
+        TransUnitType::Error(err) => return Err(Box::new(err)),
+    }
+
+    Ok(())
+}
+
+async fn ls<B: MailboxBackend>(client: &BtpClient<B>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let entries = client.entries().await?;
+
+    if entries.is_empty() {
+        println!("mailbox is empty");
+        return Ok(());
+    }
+
+    let now = Utc::now().timestamp_millis();
+
+    println!(
+        "{:<36}  {:<10}  {:<6}  {:>8}  BODY",
+        "ID", "STATE", "TYPE", "AGE"
+    );
+
+    for entry in entries.iter() {
+        println!(
+            "{:<36}  {:<10}  {:<6}  {:>8}  {}",
+            entry.head.id,
+            entry.head.state.to_string(),
+            entry.head.content_type.as_deref().unwrap_or("-"),
+            format_age(now.saturating_sub(entry.head.timestamp)),
+            entry.body
+        );
+    }
+
+    Ok(())
+}
+
+async fn cancel<B: MailboxBackend>(
+    client: &BtpClient<B>,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let id = match args {
+        [id] => id,
+        _ => usage(),
+    };
+
+    client.cancel(id).await?;
+
+    println!("cancelled {}", id);
+
+    Ok(())
+}
+
+async fn get_file<B: MailboxBackend>(
+    client: &BtpClient<B>,
+    args: &[String],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (blob, path) = match args {
+        [blob] => (blob, blob),
+        [blob, path] => (blob, path),
+        _ => usage(),
+    };
+
+    let data = client.backend().get_blob(blob).await?;
+
+    fs::write(path, &data)?;
+    println!("saved {} bytes to {}", data.len(), path);
+
+    Ok(())
+}
+
+async fn purge<B: MailboxBackend>(
+    client: &BtpClient<B>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let count = client.purge().await?;
+
+    println!("purged {} entries", count);
+
+    Ok(())
+}
+
+fn keygen(args: &[String]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let keys = KeyPair::generate();
+    let signing_key = SigningKey::generate(args.first().map(|arg| &arg[..]).unwrap_or("client"));
+
+    println!("secret_key: {}", keys.secret_base64());
+    println!("public_key: {}", keys.public_key().to_base64());
+    println!("key_id: {}", signing_key.key_id());
+    println!("signing_key: {}", signing_key.secret_base64());
+    println!("verify_key: {}", signing_key.verify_key_base64());
+
+    Ok(())
+}
+
+/// 把毫秒数格式化成 `1h05m`、`3m12s` 这样的简短时长，时间戳在未来时显示 `?`
+fn format_age(millis: i64) -> String {
+    if millis < 0 {
+        return "?".to_string();
+    }
+
+    let secs = millis / 1000;
+
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_are_formatted() {
+        assert_eq!(format_age(12_345), "12s");
+        assert_eq!(format_age(192_000), "3m12s");
+        assert_eq!(format_age(3_900_000), "1h05m");
+
+        // 时间戳在未来，或者超出范围时相减会饱和，不会溢出
+        assert_eq!(format_age(-1), "?");
+        assert_eq!(format_age(0i64.saturating_sub(i64::MAX)), "?");
+        assert!(format_age(0i64.saturating_sub(i64::MIN)).ends_with('m'));
+    }
+}
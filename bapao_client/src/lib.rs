@@ -0,0 +1,474 @@
+pub use bapao_trans_protocal::auth::{AuthError, SigningKey};
+use bapao_trans_protocal::backend;
+use bapao_trans_protocal::chunk::{self, ChunkManifest};
+pub use bapao_trans_protocal::chunk::ChunkMismatch;
+pub use bapao_trans_protocal::clock::{Clock, ManualClock, SystemClock};
+use bapao_trans_protocal::crypto;
+pub use bapao_trans_protocal::crypto::{CryptoError, KeyPair, PublicKey};
+pub use bapao_trans_protocal::backend::{GiteeBackend, MailboxBackend, ShardedBackend};
+pub use bapao_trans_protocal::config::{BapaoConfig, ConfigError};
+pub use bapao_trans_protocal::error::BtpError;
+pub use bapao_trans_protocal::retry::RetryPolicy;
+pub use bapao_trans_protocal::trans_content::{
+    ErrorCode, ReqContent, State, TransError, TransHead, TransUnitType,
+};
+use bapao_trans_protocal::clock;
+use serde_json::Value;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc, time::Duration};
+use uuid::Uuid;
+
+/// Identifier of a request written by `BtpClient::send`.
+pub type RequestId = String;
+
+/// Error returned by `BtpClient::await_response` when no response arrived in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseTimeout {
+    pub id: RequestId,
+}
+
+impl fmt::Display for ResponseTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out waiting for the response to {}", self.id)
+    }
+}
+
+impl Error for ResponseTimeout {}
+
+/// Client side of the Bapao communication system.
+///
+/// `BtpClient` writes requests into the mailbox and waits for the listener on
+/// the internal machine to answer them. It takes care of the request header
+/// (`id`, `state`, `timestamp`) and of downloading the blob behind file
+/// responses, so callers get the same `TransUnitType` the handler returned.
+///
+/// # Type Parameters
+///
+/// * `B` - The mailbox backend requests are written to, `GiteeBackend` by default.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use bapao_client::{BtpClient, TransUnitType};
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let client = BtpClient::new();
+///
+///     let id = client
+///         .send("/monitor/pic/shot?resolution=1440*720", None)
+///         .await
+///         .unwrap();
+///
+///     match client.await_response(&id, Duration::from_secs(60)).await.unwrap() {
+///         TransUnitType::File(data) => std::fs::write("shot.jpg", data).unwrap(),
+///         TransUnitType::String(text) => println!("{}", text),
+///         TransUnitType::Error(err) => eprintln!("request failed: {}", err),
+///     }
+/// }
+/// ```
+pub struct BtpClient<B = GiteeBackend> {
+    backend: B,
+    poll_interval: Duration,
+    encryption: Option<(KeyPair, PublicKey)>,
+    signing_key: Option<SigningKey>,
+    clock: Arc<dyn Clock>,
+}
+
+impl BtpClient<GiteeBackend> {
+    /// Creates a client for the Gitee repository configured in `bapao.config.json`.
+    pub fn new() -> Self {
+        BtpClient::with_backend(GiteeBackend::new())
+    }
+}
+
+impl Default for BtpClient<GiteeBackend> {
+    fn default() -> Self {
+        BtpClient::new()
+    }
+}
+
+impl<B> BtpClient<B>
+where
+    B: MailboxBackend,
+{
+    /// Creates a client that talks to the listener through `backend`.
+    pub fn with_backend(backend: B) -> Self {
+        BtpClient {
+            backend,
+            poll_interval: Duration::from_secs(5),
+            encryption: None,
+            signing_key: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// Sets how often `await_response` re-reads the mailbox (5 seconds by default).
+    ///
+    /// Polling slows down further while the backend reports a low API budget,
+    /// see `MailboxBackend::rate_budget`.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Encrypts requests to the listener key `agent_key` and decrypts responses
+    /// with `keys`.
+    ///
+    /// The public key of `keys` is sent inside every encrypted request, so the
+    /// listener does not need to know the client in advance. Encrypted
+    /// responses can only be read by a client created with this option.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use bapao_client::{BapaoConfig, BtpClient, GiteeBackend, KeyPair, PublicKey};
+    ///
+    /// let config = BapaoConfig::load_default().unwrap();
+    /// let keys = KeyPair::from_config(&config).unwrap().unwrap();
+    /// let agent_key = PublicKey::from_config(&config).unwrap().unwrap();
+    ///
+    /// let client = BtpClient::with_backend(GiteeBackend::with_config(config))
+    ///     .with_encryption(keys, agent_key);
+    /// ```
+    pub fn with_encryption(mut self, keys: KeyPair, agent_key: PublicKey) -> Self {
+        self.encryption = Some((keys, agent_key));
+        self
+    }
+
+    /// Signs every request with `key`.
+    ///
+    /// Required when the listener only accepts requests from trusted clients,
+    /// see `BtpListener::set_trust_list`. The verify key of `key` has to be in
+    /// the listener's trust list under `key.key_id()`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use bapao_client::{BapaoConfig, BtpClient, GiteeBackend, SigningKey};
+    ///
+    /// let config = BapaoConfig::load_default().unwrap();
+    /// let key = SigningKey::from_config(&config).unwrap().unwrap();
+    ///
+    /// let client = BtpClient::with_backend(GiteeBackend::with_config(config)).with_signing_key(key);
+    /// ```
+    pub fn with_signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
+    /// Sets the clock used for request timestamps and for waiting in
+    /// `await_response` and between conflicting mailbox writes.
+    ///
+    /// Defaults to the system clock; a `ManualClock` lets tests wait for a
+    /// response without sleeping.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the mailbox backend used by this client.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Appends a `Pending` request for `route` to the mailbox.
+    ///
+    /// # Parameters
+    ///
+    /// * `route` - The route registered on the listener, e.g. `/monitor/pic/shot`,
+    ///   optionally followed by query parameters (`?resolution=1440*720`)
+    /// * `payload` - Optional JSON data handed to the route handler
+    ///
+    /// # Returns
+    ///
+    /// The id of the new request, to be passed to `await_response`.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the mailbox cannot be read or written, with a `BtpError` for
+    /// `GiteeBackend` (use `BtpError::from` on the error of other backends to
+    /// classify it). A mailbox modified by someone else between the read and
+    /// the write is re-read and the request appended again, see
+    /// `backend::update_mailbox`.
+    pub async fn send(
+        &self,
+        route: &str,
+        payload: Option<Value>,
+    ) -> Result<RequestId, Box<dyn Error + Send + Sync>> {
+        self.send_with_headers(route, payload, HashMap::new()).await
+    }
+
+    /// Same as `send`, additionally attaching `headers` to the request header.
+    pub async fn send_with_headers(
+        &self,
+        route: &str,
+        payload: Option<Value>,
+        headers: HashMap<String, String>,
+    ) -> Result<RequestId, Box<dyn Error + Send + Sync>> {
+        let id = Uuid::new_v4().to_string();
+
+        let mut request = ReqContent {
+            head: TransHead {
+                id: id.clone(),
+                content_type: None,
+                state: State::Pending,
+                timestamp: self.clock.now().timestamp_millis(),
+                headers,
+                // 每个请求一个随机 nonce，监听端据此拒绝重放的请求
+                nonce: Some(Uuid::new_v4().to_string()),
+                ..Default::default()
+            },
+            body: route.to_string(),
+            payload,
+        };
+
+        if let Some((keys, agent_key)) = &self.encryption {
+            crypto::seal_request(&mut request, agent_key, &keys.public_key());
+        }
+
+        // 签名覆盖加密之后的内容
+        if let Some(key) = &self.signing_key {
+            key.sign(&mut request);
+        }
+
+        backend::update_mailbox_with_clock(&self.backend, self.clock.as_ref(), |mut content| {
+            content.push(request.clone());
+
+            Ok((Some(content), ()))
+        })
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Returns every entry currently in the mailbox, requests and responses alike.
+    pub async fn entries(&self) -> Result<Vec<ReqContent>, Box<dyn Error + Send + Sync>> {
+        let (content, _) = self.backend.read_mailbox().await?;
+
+        Ok(content)
+    }
+
+    /// Removes every finished entry from the mailbox, together with the blobs of
+    /// file responses.
+    ///
+    /// `Pending` and `Processing` requests are kept. Blobs are deleted after the mailbox has been
+    /// written, so a failed write never leaves responses pointing at missing
+    /// blobs; a blob that fails to delete is reported and skipped. Only names
+    /// the listener generates are deleted, see `backend::is_blob_name`.
+    ///
+    /// # Returns
+    ///
+    /// The number of entries removed from the mailbox.
+    pub async fn purge(&self) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let clock = self.clock.as_ref();
+
+        let purged = backend::update_mailbox_with_clock(&self.backend, clock, |content| {
+            let (kept, purged): (Vec<ReqContent>, Vec<ReqContent>) = content
+                .into_iter()
+                .partition(|item| !item.head.state.is_terminal());
+
+            if purged.is_empty() {
+                return Ok((None, purged));
+            }
+
+            Ok((Some(kept), purged))
+        })
+        .await?;
+
+        for item in purged.iter() {
+            for blob in blob_names(item) {
+                if let Err(err) = self.backend.delete_blob(&blob).await {
+                    eprintln!("删除文件 {} 出错：{}", blob, err);
+                }
+            }
+        }
+
+        Ok(purged.len())
+    }
+
+    /// Cancels request `id`, provided the listener has not accepted it yet.
+    ///
+    /// # Errors
+    ///
+    /// Fails when there is no `Pending` request with this id, e.g. because the
+    /// listener already marked it `Processing`.
+    pub async fn cancel(&self, id: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        backend::update_mailbox_with_clock(&self.backend, self.clock.as_ref(), |mut content| {
+            match content
+                .iter_mut()
+                .find(|item| item.head.id == id && item.head.state == State::Pending)
+            {
+                Some(item) => item.head.state = State::Cancelled,
+                None => return Err(Box::from(format!("no pending request with id {}", id))),
+            }
+
+            Ok((Some(content), ()))
+        })
+        .await
+    }
+
+    /// Looks up the response to request `id` once, without waiting.
+    ///
+    /// # Returns
+    ///
+    /// `None` while the request is `Pending` or `Processing`, otherwise the
+    /// response. File responses are downloaded from their blob before being
+    /// returned; chunked file responses are reassembled from their chunks and
+    /// verified against their manifest. `Error` responses, as well as
+    /// `Expired` and `Cancelled` requests, are returned as `TransUnitType::Error`.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the mailbox or a blob cannot be read, with `ChunkMismatch`
+    /// when a chunked file does not match its manifest, or with `CryptoError`
+    /// when an encrypted response cannot be decrypted. A client created with
+    /// `with_encryption` also fails with `CryptoError` on a `Done` response
+    /// that is not encrypted. Plaintext `Error` responses are returned: the
+    /// listener cannot encrypt the rejection of a request it could not verify
+    /// or decrypt, so their message is not authenticated.
+    pub async fn fetch_response(
+        &self,
+        id: &str,
+    ) -> Result<Option<TransUnitType>, Box<dyn Error + Send + Sync>> {
+        let (content, _) = self.backend.read_mailbox().await?;
+
+        let mut response = match content
+            .into_iter()
+            .find(|item| item.head.id == id && item.head.state.is_terminal())
+        {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+
+        let is_file = matches!(
+            response.head.content_type.as_deref(),
+            Some("file") | Some(chunk::CHUNKED_FILE)
+        );
+
+        // 配置了密钥时，监听端生成的响应必须是加密的，否则可能是伪造的；
+        // 无法验证或解密的请求只能以明文的错误拒绝
+        if self.encryption.is_some()
+            && response.head.cipher.is_none()
+            && response.head.state == State::Done
+        {
+            return Err(Box::new(CryptoError(String::from(
+                "response is not encrypted but a key pair is configured",
+            ))));
+        }
+
+        // 文件响应加密的是文件内容，其余响应加密的是 body
+        if !is_file && response.head.cipher.is_some() {
+            response.body = crypto::open_text(self.keys()?, &response.head, &response.body)?;
+        }
+
+        let err = match response.head.state {
+            State::Error => Some(
+                serde_json::from_str(&response.body)
+                    .unwrap_or_else(|_| TransError::new(ErrorCode::Internal, &response.body[..])),
+            ),
+            State::Expired => Some(TransError::new(
+                ErrorCode::Expired,
+                "request expired before it was handled",
+            )),
+            State::Cancelled => Some(TransError::new(ErrorCode::Cancelled, "request was cancelled")),
+            _ => None,
+        };
+
+        if let Some(err) = err {
+            return Ok(Some(TransUnitType::Error(err)));
+        }
+
+        match response.head.content_type.as_deref() {
+            Some("file") => {
+                let data = self.backend.get_blob(&response.body).await?;
+
+                Ok(Some(TransUnitType::File(self.open_file(&response.head, data)?)))
+            }
+            Some(chunk::CHUNKED_FILE) => {
+                let manifest: ChunkManifest = serde_json::from_str(&response.body)?;
+
+                // 下载之前先检查清单，伪造的清单不会触发任何下载
+                manifest.validate()?;
+
+                let mut chunks = Vec::with_capacity(manifest.chunks.len());
+
+                for name in manifest.chunks.iter() {
+                    chunks.push(self.backend.get_blob(name).await?);
+                }
+
+                let data = manifest.assemble(chunks)?;
+
+                Ok(Some(TransUnitType::File(self.open_file(&response.head, data)?)))
+            }
+            _ => Ok(Some(TransUnitType::String(response.body))),
+        }
+    }
+
+    fn keys(&self) -> Result<&KeyPair, CryptoError> {
+        match &self.encryption {
+            Some((keys, _)) => Ok(keys),
+            None => Err(CryptoError(String::from(
+                "response is encrypted but no key pair is configured",
+            ))),
+        }
+    }
+
+    fn open_file(&self, head: &TransHead, data: Vec<u8>) -> Result<Vec<u8>, CryptoError> {
+        match &head.cipher {
+            Some(cipher) => crypto::open(self.keys()?, cipher, &head.id, &data),
+            None => Ok(data),
+        }
+    }
+
+    /// Polls the mailbox until the response to request `id` arrives.
+    ///
+    /// # Parameters
+    ///
+    /// * `id` - The id returned by `send`
+    /// * `timeout` - How long to wait before giving up
+    ///
+    /// # Errors
+    ///
+    /// Returns `ResponseTimeout` when no response arrived within `timeout`, or
+    /// the backend error when the mailbox or the blob cannot be read.
+    pub async fn await_response(
+        &self,
+        id: &str,
+        timeout: Duration,
+    ) -> Result<TransUnitType, Box<dyn Error + Send + Sync>> {
+        let start = self.clock.now();
+
+        loop {
+            if let Some(response) = self.fetch_response(id).await? {
+                return Ok(response);
+            }
+
+            let now = self.clock.now();
+            let elapsed = (now - start).to_std().unwrap_or_default();
+
+            if elapsed >= timeout {
+                return Err(Box::new(ResponseTimeout { id: id.to_string() }));
+            }
+
+            // API 调用次数不多时放慢轮询
+            let interval = match self.backend.rate_budget() {
+                Some(budget) => self.poll_interval.max(budget.pacing_at(now)),
+                None => self.poll_interval,
+            };
+
+            self.clock.sleep(interval.min(timeout - elapsed)).await;
+        }
+    }
+}
+
+/// 文件类型的响应引用的所有文件名
+fn blob_names(entry: &ReqContent) -> Vec<String> {
+    match entry.head.content_type.as_deref() {
+        Some("file") => vec![entry.body.clone()],
+        Some(chunk::CHUNKED_FILE) => serde_json::from_str::<ChunkManifest>(&entry.body)
+            .map(|manifest| manifest.chunks)
+            .unwrap_or_default(),
+        _ => vec![],
+    }
+}
@@ -0,0 +1,433 @@
+use ed25519_dalek::{Signature, Signer, Verifier, VerifyingKey};
+use rand_core::OsRng;
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, fmt};
+
+use crate::config::BapaoConfig;
+use crate::trans_content::{CipherHead, ReqContent};
+
+/// Name of the config section holding the trusted client keys.
+pub const TRUSTED_CLIENTS: &str = "trusted_clients";
+
+/// 签名内容的前缀，避免签名被用在别的地方
+const SIGNATURE_CONTEXT: &[u8] = b"bapao request v1\n";
+
+/// Error returned when a key cannot be parsed or a request fails verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthError(pub String);
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "authentication error: {}", self.0)
+    }
+}
+
+impl Error for AuthError {}
+
+/// 参与签名的字段。state 会被监听端修改，不参与签名；
+/// 请求加密时签名的是密文，监听端无需解密即可验证
+#[derive(Serialize)]
+struct SignedFields<'a> {
+    id: &'a str,
+    key_id: &'a str,
+    timestamp: i64,
+    nonce: &'a Option<String>,
+    body: &'a str,
+    payload: &'a Option<serde_json::Value>,
+    headers: &'a HashMap<String, String>,
+    cipher: &'a Option<CipherHead>,
+}
+
+/// 按照 JSON 值序列化，字段和 headers 的顺序固定
+fn signed_message(request: &ReqContent, key_id: &str) -> Vec<u8> {
+    let fields = SignedFields {
+        id: &request.head.id,
+        key_id,
+        timestamp: request.head.timestamp,
+        nonce: &request.head.nonce,
+        body: &request.body,
+        payload: &request.payload,
+        headers: &request.head.headers,
+        cipher: &request.head.cipher,
+    };
+
+    let value = serde_json::to_value(&fields).expect("a JSON value always serializes");
+
+    let mut message = SIGNATURE_CONTEXT.to_vec();
+    message.extend(value.to_string().into_bytes());
+    message
+}
+
+fn decode_key(value: &str) -> Result<[u8; 32], AuthError> {
+    let bytes = base64::decode(value.trim()).map_err(|err| AuthError(err.to_string()))?;
+
+    bytes
+        .try_into()
+        .map_err(|_| AuthError(String::from("key must be 32 bytes")))
+}
+
+/// Ed25519 key a client signs its requests with.
+///
+/// Every client has its own key, identified by `key_id`. The listener only
+/// needs the verify key (the public half), which is added to its `TrustList`
+/// under the same id.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::auth::{SigningKey, TrustList};
+/// use bapao_trans_protocal::trans_content::{ReqContent, TransHead};
+///
+/// let key = SigningKey::generate("laptop");
+///
+/// let mut request = ReqContent {
+///     head: TransHead { id: "req_001".to_string(), ..Default::default() },
+///     body: "/ping".to_string(),
+///     payload: None,
+/// };
+/// key.sign(&mut request);
+///
+/// let mut trusted = TrustList::new();
+/// trusted.add("laptop", &key.verify_key_base64()).unwrap();
+///
+/// assert_eq!(trusted.verify(&request).unwrap(), "laptop");
+/// ```
+#[derive(Clone)]
+pub struct SigningKey {
+    key_id: String,
+    key: ed25519_dalek::SigningKey,
+}
+
+impl SigningKey {
+    /// Generates a new random key with the id `key_id`.
+    pub fn generate(key_id: &str) -> Self {
+        SigningKey {
+            key_id: key_id.to_string(),
+            key: ed25519_dalek::SigningKey::generate(&mut OsRng),
+        }
+    }
+
+    /// Restores a key from its id and its base64-encoded 32-byte secret.
+    pub fn from_base64(key_id: &str, secret: &str) -> Result<Self, AuthError> {
+        Ok(SigningKey {
+            key_id: key_id.to_string(),
+            key: ed25519_dalek::SigningKey::from_bytes(&decode_key(secret)?),
+        })
+    }
+
+    /// Reads the key from `key_id` and `signing_key` in `config`, `None` when
+    /// no signing key is configured.
+    ///
+    /// # Errors
+    ///
+    /// Fails when `signing_key` is set without `key_id`, or cannot be parsed.
+    pub fn from_config(config: &BapaoConfig) -> Result<Option<Self>, AuthError> {
+        match (&config.key_id, &config.signing_key) {
+            (Some(key_id), Some(secret)) => Ok(Some(SigningKey::from_base64(key_id, secret)?)),
+            (None, Some(_)) => Err(AuthError(String::from(
+                "signing_key is set but key_id is missing",
+            ))),
+            (_, None) => Ok(None),
+        }
+    }
+
+    /// Returns the id of this key.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Returns the secret key as base64 text, to be stored in the config file.
+    pub fn secret_base64(&self) -> String {
+        base64::encode(self.key.to_bytes())
+    }
+
+    /// Returns the verify key as base64 text, to be added to the listener's trust list.
+    pub fn verify_key_base64(&self) -> String {
+        base64::encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// Signs `request`, setting `key_id` and `signature` in its head.
+    ///
+    /// The signature covers the id, timestamp, nonce, route, payload, headers
+    /// and cipher of the request, but not its state. Encrypted requests must be
+    /// signed after they were encrypted.
+    pub fn sign(&self, request: &mut ReqContent) {
+        let signature = self.key.sign(&signed_message(request, &self.key_id));
+
+        request.head.key_id = Some(self.key_id.clone());
+        request.head.signature = Some(base64::encode(signature.to_bytes()));
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 不输出私钥
+        f.debug_struct("SigningKey")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+/// Verify keys of the clients a listener accepts requests from, by key id.
+///
+/// Configured in `bapao.config.json` as an object mapping key ids to
+/// base64 verify keys:
+///
+/// ```json
+/// { "trusted_clients": { "laptop": "<verify key>" } }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TrustList {
+    keys: HashMap<String, VerifyingKey>,
+}
+
+impl TrustList {
+    /// Creates an empty trust list, which rejects every request.
+    pub fn new() -> Self {
+        TrustList::default()
+    }
+
+    /// Reads the trust list from the `trusted_clients` section of `config`,
+    /// `None` when the section is missing.
+    pub fn from_config(config: &BapaoConfig) -> Result<Option<Self>, Box<dyn Error + Send + Sync>> {
+        let section: HashMap<String, String> = match config.section(TRUSTED_CLIENTS)? {
+            Some(section) => section,
+            None => return Ok(None),
+        };
+
+        let mut trusted = TrustList::new();
+
+        for (key_id, verify_key) in section.iter() {
+            trusted.add(key_id, verify_key)?;
+        }
+
+        Ok(Some(trusted))
+    }
+
+    /// Trusts the base64 verify key `verify_key` under `key_id`, replacing any
+    /// key previously added under that id.
+    pub fn add(&mut self, key_id: &str, verify_key: &str) -> Result<(), AuthError> {
+        let key = VerifyingKey::from_bytes(&decode_key(verify_key)?)
+            .map_err(|err| AuthError(err.to_string()))?;
+
+        self.keys.insert(key_id.to_string(), key);
+
+        Ok(())
+    }
+
+    /// Removes the key trusted under `key_id`, returning whether there was one.
+    pub fn remove(&mut self, key_id: &str) -> bool {
+        self.keys.remove(key_id).is_some()
+    }
+
+    /// Checks the signature of `request` against the trusted keys.
+    ///
+    /// # Returns
+    ///
+    /// The key id of the client that signed the request.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the request is unsigned, its key id is not trusted, or the
+    /// signature does not match.
+    pub fn verify(&self, request: &ReqContent) -> Result<String, AuthError> {
+        let (key_id, signature) = match (&request.head.key_id, &request.head.signature) {
+            (Some(key_id), Some(signature)) => (key_id, signature),
+            _ => return Err(AuthError(String::from("request is not signed"))),
+        };
+
+        let key = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| AuthError(format!("unknown key id {}", key_id)))?;
+
+        let signature = base64::decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| AuthError(String::from("malformed signature")))?;
+
+        key.verify(&signed_message(request, key_id), &signature)
+            .map_err(|_| AuthError(String::from("signature does not match")))?;
+
+        Ok(key_id.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trans_content::{State, TransHead};
+
+    fn request() -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: "req_001".to_string(),
+                timestamp: 1704067200000,
+                nonce: Some("nonce".to_string()),
+                headers: HashMap::from([("client".to_string(), "laptop".to_string())]),
+                ..Default::default()
+            },
+            body: "/ping".to_string(),
+            payload: Some(serde_json::json!({ "count": 1 })),
+        }
+    }
+
+    fn signed(key: &SigningKey) -> ReqContent {
+        let mut request = request();
+        key.sign(&mut request);
+        request
+    }
+
+    fn trusting(key: &SigningKey) -> TrustList {
+        let mut trusted = TrustList::new();
+        trusted.add(key.key_id(), &key.verify_key_base64()).unwrap();
+        trusted
+    }
+
+    fn config(extra: &str) -> BapaoConfig {
+        BapaoConfig::parse(&format!(
+            r#"{{ "access_token": "token", "user_name": "bapao", "repo": "mailbox", "file_path": "io"{} }}"#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn signed_request_is_verified() {
+        let key = SigningKey::generate("laptop");
+        let request = signed(&key);
+
+        assert_eq!(request.head.key_id.as_deref(), Some("laptop"));
+        assert_eq!(trusting(&key).verify(&request).unwrap(), "laptop");
+    }
+
+    #[test]
+    fn state_is_not_signed() {
+        let key = SigningKey::generate("laptop");
+        let mut request = signed(&key);
+
+        // 监听端会修改 state
+        request.head.state = State::Processing;
+
+        assert!(trusting(&key).verify(&request).is_ok());
+    }
+
+    #[test]
+    fn tampered_body_is_rejected() {
+        let key = SigningKey::generate("laptop");
+        let trusted = trusting(&key);
+
+        let mut request = signed(&key);
+        request.body = "/shell/rm".to_string();
+        assert!(trusted.verify(&request).is_err());
+
+        let mut request = signed(&key);
+        request.payload = Some(serde_json::json!({ "count": 2 }));
+        assert!(trusted.verify(&request).is_err());
+    }
+
+    #[test]
+    fn tampered_head_is_rejected() {
+        let key = SigningKey::generate("laptop");
+        let trusted = trusting(&key);
+
+        let tampered: Vec<fn(&mut TransHead)> = vec![
+            |head| head.id = "req_002".to_string(),
+            |head| head.timestamp += 1,
+            |head| head.nonce = None,
+            |head| {
+                head.headers
+                    .insert("client".to_string(), "desktop".to_string());
+            },
+        ];
+
+        for tamper in tampered {
+            let mut request = signed(&key);
+            tamper(&mut request.head);
+
+            let err = trusted.verify(&request).unwrap_err();
+            assert_eq!(err.0, "signature does not match");
+        }
+    }
+
+    #[test]
+    fn untrusted_or_unsigned_requests_are_rejected() {
+        let key = SigningKey::generate("laptop");
+        let other = SigningKey::generate("laptop");
+
+        // 同一个 key id，不同的密钥
+        assert!(trusting(&other).verify(&signed(&key)).is_err());
+
+        let err = TrustList::new().verify(&signed(&key)).unwrap_err();
+        assert_eq!(err.0, "unknown key id laptop");
+
+        let err = trusting(&key).verify(&request()).unwrap_err();
+        assert_eq!(err.0, "request is not signed");
+
+        let mut request = signed(&key);
+        request.head.signature = Some("not base64".to_string());
+        assert_eq!(
+            trusting(&key).verify(&request).unwrap_err().0,
+            "malformed signature"
+        );
+    }
+
+    #[test]
+    fn removed_key_is_no_longer_trusted() {
+        let key = SigningKey::generate("laptop");
+        let mut trusted = trusting(&key);
+
+        assert!(trusted.remove("laptop"));
+        assert!(!trusted.remove("laptop"));
+        assert!(trusted.verify(&signed(&key)).is_err());
+    }
+
+    #[test]
+    fn signing_key_survives_base64() {
+        let key = SigningKey::generate("laptop");
+        let restored = SigningKey::from_base64("laptop", &key.secret_base64()).unwrap();
+
+        assert_eq!(restored.verify_key_base64(), key.verify_key_base64());
+        assert!(SigningKey::from_base64("laptop", "c2hvcnQ=").is_err());
+        assert!(!format!("{:?}", key).contains(&key.secret_base64()));
+    }
+
+    #[test]
+    fn trust_list_is_read_from_the_config() {
+        let key = SigningKey::generate("laptop");
+
+        let trusted = TrustList::from_config(&config(&format!(
+            r#", "trusted_clients": {{ "laptop": "{}" }}"#,
+            key.verify_key_base64()
+        )))
+        .unwrap()
+        .unwrap();
+        assert_eq!(trusted.verify(&signed(&key)).unwrap(), "laptop");
+
+        assert!(TrustList::from_config(&config("")).unwrap().is_none());
+        assert!(
+            TrustList::from_config(&config(r#", "trusted_clients": { "laptop": "bad" }"#)).is_err()
+        );
+        assert!(TrustList::from_config(&config(r#", "trusted_clients": ["laptop"]"#)).is_err());
+    }
+
+    #[test]
+    fn signing_key_is_read_from_the_config() {
+        let key = SigningKey::generate("laptop");
+        let secret = key.secret_base64();
+
+        let restored = SigningKey::from_config(&config(&format!(
+            r#", "key_id": "laptop", "signing_key": "{}""#,
+            secret
+        )))
+        .unwrap()
+        .unwrap();
+        assert_eq!(restored.key_id(), "laptop");
+        assert_eq!(restored.verify_key_base64(), key.verify_key_base64());
+
+        assert!(SigningKey::from_config(&config("")).unwrap().is_none());
+        assert!(
+            SigningKey::from_config(&config(&format!(r#", "signing_key": "{}""#, secret))).is_err()
+        );
+    }
+}
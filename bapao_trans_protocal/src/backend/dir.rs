@@ -0,0 +1,420 @@
+use async_trait::async_trait;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use super::{check_blob_name, revision_of, MailboxBackend, RevisionConflict, ShardStore};
+use crate::error::BtpError;
+use crate::trans_content::{self, ReqContent};
+
+/// Mailbox backend keeping the mailbox and blobs in a local directory.
+///
+/// The layout mirrors the Gitee repository: the mailbox file (`io` by default)
+/// and the UUID-named blobs created for file responses all live directly in
+/// `root`. Revisions are git-style blob hashes of the mailbox file, so a client
+/// that edits the file between a read and a write is detected the same way
+/// Gitee detects a stale `sha`.
+///
+/// The directory can be shared with a client on the same machine, or kept in
+/// sync with a shared drive for air-gapped setups.
+///
+/// Used as a `ShardStore`, the files of a `ShardedBackend` are stored in
+/// subdirectories of `root`.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use bapao_trans_protocal::{backend::DirBackend, BtpListener};
+///
+/// let backend = DirBackend::new("/srv/bapao").with_mailbox_name("messages");
+/// let listener = BtpListener::with_backend(backend);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DirBackend {
+    root: PathBuf,
+    mailbox_name: String,
+}
+
+impl DirBackend {
+    /// Creates a backend storing everything under `root`, with the mailbox in `root/io`.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        DirBackend {
+            root: root.into(),
+            mailbox_name: String::from("io"),
+        }
+    }
+
+    /// Uses `name` instead of `io` as the mailbox file name.
+    pub fn with_mailbox_name(mut self, name: &str) -> Self {
+        self.mailbox_name = name.to_string();
+        self
+    }
+
+    /// Returns the directory this backend stores its files in.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn blob_path(&self, name: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        // 文件名只能是单层的普通文件名，避免写到目录外面去
+        if name.is_empty()
+            || name == "."
+            || name == ".."
+            || name.contains('/')
+            || name.contains('\\')
+            || name == self.mailbox_name
+        {
+            return Err(BtpError::InvalidInput(format!("invalid blob name: {}", name)).into());
+        }
+
+        Ok(self.root.join(name))
+    }
+
+    fn shard_path(&self, path: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        // 每一层都只能是普通的文件名，避免写到目录外面去
+        let valid = path
+            .split('/')
+            .all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'));
+
+        if !valid {
+            return Err(BtpError::InvalidInput(format!("invalid path: {}", path)).into());
+        }
+
+        Ok(self.root.join(path))
+    }
+}
+
+/// 读取文件的版本，文件不存在时返回 `None`
+fn read_revision(path: &Path) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(revision_of(&bytes))),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+/// 先写临时文件再重命名，避免同步盘读到写了一半的文件
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+
+    let tmp_path = path.with_file_name(tmp_name);
+
+    fs::write(&tmp_path, content)?;
+    fs::rename(&tmp_path, path)
+}
+
+#[async_trait]
+impl MailboxBackend for DirBackend {
+    async fn read_mailbox(&self) -> Result<(Vec<ReqContent>, String), Box<dyn Error + Send + Sync>> {
+        match fs::read(self.root.join(&self.mailbox_name)) {
+            Ok(bytes) => {
+                let content = trans_content::decode_mailbox(&bytes)?;
+
+                Ok((content, revision_of(&bytes)))
+            }
+            // 文件还不存在时当作空邮箱，第一次写入时再创建
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok((vec![], String::new())),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    async fn write_mailbox(
+        &self,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = self.root.join(&self.mailbox_name);
+
+        let current = match fs::read(&path) {
+            Ok(bytes) => revision_of(&bytes),
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        if current != revision {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        fs::create_dir_all(&self.root)?;
+        write_atomic(&path, serde_json::to_string(content)?.as_bytes())?;
+
+        Ok(())
+    }
+
+    async fn put_blob(&self, name: &str, content: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = self.blob_path(name)?;
+
+        fs::create_dir_all(&self.root)?;
+        write_atomic(&path, content)?;
+
+        Ok(())
+    }
+
+    async fn get_blob(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        Ok(fs::read(self.blob_path(name)?)?)
+    }
+
+    async fn delete_blob(&self, name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        check_blob_name(name)?;
+
+        fs::remove_file(self.blob_path(name)?)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ShardStore for DirBackend {
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let entries = match fs::read_dir(self.shard_path(dir)?) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        let mut names = vec![];
+
+        for entry in entries {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+
+        Ok(names)
+    }
+
+    async fn read(&self, path: &str) -> Result<Option<(Vec<u8>, String)>, Box<dyn Error + Send + Sync>> {
+        match fs::read(self.shard_path(path)?) {
+            Ok(bytes) => {
+                let revision = revision_of(&bytes);
+                Ok(Some((bytes, revision)))
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        content: &[u8],
+        revision: Option<&str>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = self.shard_path(path)?;
+
+        if read_revision(&path)?.as_deref() != revision {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        write_atomic(&path, content)?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str, revision: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = self.shard_path(path)?;
+
+        if read_revision(&path)?.as_deref() != Some(revision) {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trans_content::TransHead;
+    use uuid::Uuid;
+
+    const BLOB: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    fn backend() -> DirBackend {
+        DirBackend::new(std::env::temp_dir().join(format!("bapao-dir-{}", Uuid::new_v4())))
+    }
+
+    fn entry(id: &str) -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: id.to_string(),
+                ..Default::default()
+            },
+            body: "/ping".to_string(),
+            payload: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_mailbox_reads_as_empty() {
+        let backend = backend();
+
+        let (content, revision) = backend.read_mailbox().await.unwrap();
+        assert!(content.is_empty());
+        assert_eq!(revision, "");
+
+        // 第一次写入时创建目录和文件
+        backend
+            .write_mailbox(&[entry("a")], &revision)
+            .await
+            .unwrap();
+        assert!(backend.root().join("io").is_file());
+
+        fs::remove_dir_all(backend.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn mailbox_writes_are_guarded_by_the_revision() {
+        let backend = backend().with_mailbox_name("messages");
+
+        let (_, revision) = backend.read_mailbox().await.unwrap();
+        backend
+            .write_mailbox(&[entry("a")], &revision)
+            .await
+            .unwrap();
+
+        let (content, current) = backend.read_mailbox().await.unwrap();
+        assert_eq!(content[0].head.id, "a");
+        assert_eq!(
+            current,
+            revision_of(&fs::read(backend.root().join("messages")).unwrap())
+        );
+
+        // 用旧的版本写入会冲突，文件不变
+        let err = backend
+            .write_mailbox(&[entry("b")], &revision)
+            .await
+            .unwrap_err();
+        assert!(err.is::<RevisionConflict>());
+
+        // 文件在读取之后被别人修改过
+        fs::write(backend.root().join("messages"), "[]").unwrap();
+        let err = backend
+            .write_mailbox(&[entry("c")], &current)
+            .await
+            .unwrap_err();
+        assert!(err.is::<RevisionConflict>());
+
+        let (content, _) = backend.read_mailbox().await.unwrap();
+        assert!(content.is_empty());
+
+        fs::remove_dir_all(backend.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn blob_round_trip() {
+        let backend = backend();
+
+        backend.put_blob(BLOB, &[1, 2, 3]).await.unwrap();
+        assert_eq!(backend.get_blob(BLOB).await.unwrap(), vec![1, 2, 3]);
+
+        // 覆盖写入时不留下临时文件
+        backend.put_blob(BLOB, &[4]).await.unwrap();
+        assert_eq!(backend.get_blob(BLOB).await.unwrap(), vec![4]);
+        assert!(!backend.root().join(format!("{}.tmp", BLOB)).exists());
+
+        backend.delete_blob(BLOB).await.unwrap();
+        assert!(backend.get_blob(BLOB).await.is_err());
+        assert!(backend.delete_blob(BLOB).await.is_err());
+
+        fs::remove_dir_all(backend.root()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn blob_names_cannot_leave_the_directory() {
+        let backend = backend();
+        let outside = std::env::temp_dir().join(format!("bapao-outside-{}", Uuid::new_v4()));
+        let absolute = outside.to_str().unwrap().to_string();
+
+        for name in [
+            "",
+            ".",
+            "..",
+            "../escape",
+            "a/b",
+            "a\\b",
+            "io",
+            absolute.as_str(),
+        ] {
+            assert!(backend.put_blob(name, b"x").await.is_err(), "{}", name);
+            assert!(backend.get_blob(name).await.is_err(), "{}", name);
+            assert!(backend.delete_blob(name).await.is_err(), "{}", name);
+        }
+
+        assert!(!outside.exists());
+        assert!(!backend.root().exists());
+    }
+
+    #[tokio::test]
+    async fn shard_paths_cannot_leave_the_directory() {
+        let backend = backend();
+        let outside = std::env::temp_dir().join(format!("bapao-outside-{}", Uuid::new_v4()));
+        let absolute = outside.to_str().unwrap().to_string();
+
+        for path in [
+            "",
+            "../escape",
+            "a/../../escape",
+            "a//b",
+            "./a",
+            "a\\b",
+            absolute.as_str(),
+        ] {
+            assert!(backend.write(path, b"x", None).await.is_err(), "{}", path);
+            assert!(backend.read(path).await.is_err(), "{}", path);
+            assert!(backend.list(path).await.is_err(), "{}", path);
+            assert!(backend.delete(path, "").await.is_err(), "{}", path);
+        }
+
+        assert!(!outside.exists());
+        assert!(!backend.root().exists());
+    }
+
+    #[tokio::test]
+    async fn shard_writes_are_guarded_by_the_revision() {
+        let backend = backend();
+
+        backend.write("requests/a", b"one", None).await.unwrap();
+        assert!(backend
+            .write("requests/a", b"two", None)
+            .await
+            .unwrap_err()
+            .is::<RevisionConflict>());
+
+        let (content, revision) = backend.read("requests/a").await.unwrap().unwrap();
+        assert_eq!(content, b"one");
+        assert_eq!(
+            backend.list("requests").await.unwrap(),
+            vec!["a".to_string()]
+        );
+        assert!(backend.list("responses").await.unwrap().is_empty());
+
+        backend
+            .write("requests/a", b"two", Some(&revision))
+            .await
+            .unwrap();
+        assert!(backend
+            .delete("requests/a", &revision)
+            .await
+            .unwrap_err()
+            .is::<RevisionConflict>());
+
+        let (_, revision) = backend.read("requests/a").await.unwrap().unwrap();
+        backend.delete("requests/a", &revision).await.unwrap();
+        assert!(backend.read("requests/a").await.unwrap().is_none());
+
+        fs::remove_dir_all(backend.root()).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::sync::{Arc, OnceLock};
+
+use super::{check_blob_name, commit_one_by_one, MailboxBackend, ShardStore};
+use crate::clock::{self, Clock};
+use crate::config::{BapaoConfig, ConfigError};
+use crate::error::BtpError;
+use crate::gitee::fetch::{self as gitee_fetch, GiteeApi};
+use crate::retry::{RateBudget, RetryPolicy};
+use crate::trans_content::ReqContent;
+
+/// Mailbox backend storing the mailbox in a Gitee repository.
+///
+/// The mailbox is the file configured as `file_path` in `BapaoConfig`, and
+/// blobs are created as separate files in the repository root. The config is
+/// loaded once, either passed in with `with_config` or read by `new` on first
+/// use, and shared by clones of the backend together with one HTTP client, so
+/// connections are reused across polls. The client honors the `proxy`,
+/// `ca_bundle`, timeout and `user_agent` settings of the config.
+///
+/// Calls failing with a transient error are retried following a
+/// `RetryPolicy` (see `with_retry_policy`), waiting with the backend's
+/// `Clock` (see `with_clock`) in between. The rate-limit headers of every
+/// response are recorded and reported by `MailboxBackend::rate_budget`, which
+/// the listener uses to slow down polling before the budget runs out.
+///
+/// Errors are returned as `BtpError`, which maps the HTTP status of a failed
+/// call, see `error`. A mailbox modified since it was read is reported as
+/// `BtpError::Conflict`.
+///
+/// By default every blob and the mailbox update are separate commits made
+/// through the contents API. With `with_atomic_commits` they are written as a
+/// single commit through the Git Data API instead.
+#[derive(Debug, Clone)]
+pub struct GiteeBackend {
+    api: Arc<OnceLock<Result<GiteeApi, ConfigError>>>,
+    atomic_commits: bool,
+    retry: RetryPolicy,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for GiteeBackend {
+    fn default() -> Self {
+        GiteeBackend {
+            api: Arc::default(),
+            atomic_commits: false,
+            retry: RetryPolicy::default(),
+            clock: clock::system(),
+        }
+    }
+}
+
+impl GiteeBackend {
+    /// Creates a backend using the repository configured in `bapao.config.json`.
+    ///
+    /// The file (see `BapaoConfig::load_default`) is read on the first request;
+    /// when it cannot be loaded, every request fails with the `ConfigError`.
+    pub fn new() -> Self {
+        GiteeBackend::default()
+    }
+
+    /// Creates a backend using the repository configured in `config`.
+    ///
+    /// The HTTP client is built right away and shared by all requests and
+    /// clones of the backend; when `proxy` or `ca_bundle` is invalid, every
+    /// request fails with the `ConfigError`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// use bapao_trans_protocal::{backend::GiteeBackend, config::BapaoConfig, BtpListener};
+    ///
+    /// let config = BapaoConfig::load("/etc/bapao/bapao.config.json").unwrap();
+    /// let listener = BtpListener::with_backend(GiteeBackend::with_config(config));
+    /// ```
+    pub fn with_config(config: BapaoConfig) -> Self {
+        GiteeBackend {
+            api: Arc::new(OnceLock::from(GiteeApi::new(config))),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the config used by this backend, loading it if necessary.
+    pub fn config(&self) -> Result<&BapaoConfig, ConfigError> {
+        self.api().map(|api| &api.config)
+    }
+
+    /// Sets how failed API calls are retried.
+    ///
+    /// By default a call is made up to 4 times, waiting about 1, 2 and 4
+    /// seconds in between, see `RetryPolicy`. Use `RetryPolicy::never()` to
+    /// fail on the first error.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use bapao_trans_protocal::{backend::GiteeBackend, retry::RetryPolicy};
+    /// use std::time::Duration;
+    ///
+    /// let backend = GiteeBackend::new().with_retry_policy(RetryPolicy {
+    ///     max_attempts: 6,
+    ///     max_delay: Duration::from_secs(60),
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Waits between retries with `clock`, e.g. a `clock::ManualClock` in tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 按照重试策略调用 API，两次调用之间用 backend 的时钟等待
+    async fn run<T, F, Fut>(&self, call: F) -> Result<T, BtpError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BtpError>>,
+    {
+        self.retry.run_with_clock(self.clock.as_ref(), call).await
+    }
+
+    fn api(&self) -> Result<&GiteeApi, ConfigError> {
+        self.api
+            .get_or_init(|| BapaoConfig::load_default().and_then(GiteeApi::new))
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    /// 配置加载失败时转换成 `BtpError::Config`，和请求的错误类型一致
+    fn repo_api(&self) -> Result<&GiteeApi, BtpError> {
+        Ok(self.api()?)
+    }
+
+    /// Writes the blobs of a cycle and the mailbox update as one commit.
+    ///
+    /// Blobs, a tree and a commit are created through the Git Data API, and the
+    /// branch (`BapaoConfig::branch`, `master` by default) is then
+    /// moved to that commit. A cycle with N file responses takes one commit
+    /// instead of N+1, and a failure leaves the repository untouched, so no
+    /// response ever points at a blob that was not uploaded. The server must
+    /// support creating git objects (`POST git/blobs`, `git/trees`, `git/commits`).
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use bapao_trans_protocal::{backend::GiteeBackend, BtpListener};
+    ///
+    /// let listener = BtpListener::with_backend(GiteeBackend::new().with_atomic_commits());
+    /// ```
+    pub fn with_atomic_commits(mut self) -> Self {
+        self.atomic_commits = true;
+        self
+    }
+}
+
+#[async_trait]
+impl MailboxBackend for GiteeBackend {
+    async fn read_mailbox(&self) -> Result<(Vec<ReqContent>, String), Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        Ok(self.run(|| gitee_fetch::get_content(api)).await?)
+    }
+
+    async fn write_mailbox(
+        &self,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+        let content = serde_json::to_string(content)?;
+
+        // 写入超时但实际已成功时，重试会因为 sha 过期而冲突，由 update_mailbox 重新读取
+        Ok(self
+            .run(|| gitee_fetch::put_content(api, content.clone(), revision.to_string()))
+            .await?)
+    }
+
+    async fn put_blob(&self, name: &str, content: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        // blob 名称是新生成的 uuid，已存在说明上一次上传超时但实际已经成功
+        match self.run(|| gitee_fetch::create_file(api, name, content)).await {
+            Ok(()) | Err(BtpError::AlreadyExists(_)) => Ok(()),
+            Err(err) => Err(Box::new(err)),
+        }
+    }
+
+    async fn get_blob(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        Ok(self.run(|| gitee_fetch::get_file(api, name)).await?)
+    }
+
+    async fn delete_blob(&self, name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        check_blob_name(name)?;
+
+        let api = self.repo_api()?;
+
+        Ok(self.run(|| gitee_fetch::delete_file(api, name)).await?)
+    }
+
+    async fn commit(
+        &self,
+        blobs: &mut HashMap<String, Vec<u8>>,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // 没有文件时只有一次提交，用 contents API 就够了
+        if !self.atomic_commits || blobs.is_empty() {
+            return commit_one_by_one(self, blobs, content, revision).await;
+        }
+
+        let api = self.repo_api()?;
+        let content = serde_json::to_string(content)?;
+        let files: &HashMap<String, Vec<u8>> = blobs;
+
+        self.run(|| gitee_fetch::commit_files(api, files, content.clone(), revision))
+            .await?;
+        blobs.clear();
+
+        Ok(())
+    }
+
+    fn rate_budget(&self) -> Option<RateBudget> {
+        self.api().ok()?.rate_budget()
+    }
+}
+
+#[async_trait]
+impl ShardStore for GiteeBackend {
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        Ok(self.run(|| gitee_fetch::list_dir(api, dir)).await?)
+    }
+
+    async fn read(&self, path: &str) -> Result<Option<(Vec<u8>, String)>, Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        Ok(self.run(|| gitee_fetch::get_file_with_sha(api, path)).await?)
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        content: &[u8],
+        revision: Option<&str>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        // 要新建的文件已被别人创建，和 sha 过期一样重新读取
+        match self.run(|| gitee_fetch::put_file(api, path, content, revision)).await {
+            Err(BtpError::AlreadyExists(message)) => Err(Box::new(BtpError::Conflict(message))),
+            result => Ok(result?),
+        }
+    }
+
+    async fn delete(&self, path: &str, revision: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let api = self.repo_api()?;
+
+        Ok(self.run(|| gitee_fetch::delete_file_with_sha(api, path, revision)).await?)
+    }
+
+    fn rate_budget(&self) -> Option<RateBudget> {
+        MailboxBackend::rate_budget(self)
+    }
+}
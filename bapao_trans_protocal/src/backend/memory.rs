@@ -0,0 +1,288 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use super::{check_blob_name, revision_of, MailboxBackend, RevisionConflict, ShardStore};
+use crate::error::BtpError;
+use crate::trans_content::{self, ReqContent};
+
+#[derive(Debug)]
+struct MemoryState {
+    mailbox: String,
+    blobs: HashMap<String, Vec<u8>>,
+}
+
+/// Mailbox backend keeping the mailbox and blobs in memory.
+///
+/// Clones share the same storage, so a listener and a test client can talk to
+/// each other through one `MemoryBackend` without touching the network. Writes
+/// are checked against the revision of the serialized mailbox exactly like the
+/// Gitee `sha` check.
+///
+/// Used as a `ShardStore`, the files of a `ShardedBackend` are kept as blobs
+/// named by their path, e.g. `requests/<id>.json`.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::{backend::MemoryBackend, BtpListener};
+///
+/// let backend = MemoryBackend::new();
+/// let listener = BtpListener::with_backend(backend.clone());
+/// assert!(backend.blob_names().is_empty());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MemoryBackend {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryBackend {
+    /// Creates a backend with an empty mailbox (`[]`) and no blobs.
+    pub fn new() -> Self {
+        MemoryBackend {
+            state: Arc::new(Mutex::new(MemoryState {
+                mailbox: String::from("[]"),
+                blobs: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns a copy of the blob stored under `name`, if any.
+    pub fn blob(&self, name: &str) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().blobs.get(name).cloned()
+    }
+
+    /// Returns the names of all stored blobs.
+    pub fn blob_names(&self) -> Vec<String> {
+        self.state.lock().unwrap().blobs.keys().cloned().collect()
+    }
+
+    /// Returns the raw mailbox text, as it would be stored in the `io` file.
+    pub fn raw_mailbox(&self) -> String {
+        self.state.lock().unwrap().mailbox.clone()
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend::new()
+    }
+}
+
+#[async_trait]
+impl MailboxBackend for MemoryBackend {
+    async fn read_mailbox(&self) -> Result<(Vec<ReqContent>, String), Box<dyn Error + Send + Sync>> {
+        let state = self.state.lock().unwrap();
+
+        let content = trans_content::decode_mailbox(state.mailbox.as_bytes())?;
+
+        Ok((content, revision_of(state.mailbox.as_bytes())))
+    }
+
+    async fn write_mailbox(
+        &self,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+
+        if revision_of(state.mailbox.as_bytes()) != revision {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        state.mailbox = serde_json::to_string(content)?;
+
+        Ok(())
+    }
+
+    async fn put_blob(&self, name: &str, content: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+
+        state.blobs.insert(name.to_string(), content.to_vec());
+
+        Ok(())
+    }
+
+    async fn get_blob(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.blob(name)
+            .ok_or_else(|| BtpError::NotFound(format!("blob {}", name)).into())
+    }
+
+    async fn delete_blob(&self, name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        check_blob_name(name)?;
+
+        let mut state = self.state.lock().unwrap();
+
+        match state.blobs.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(BtpError::NotFound(format!("blob {}", name)).into()),
+        }
+    }
+}
+
+#[async_trait]
+impl ShardStore for MemoryBackend {
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+        let state = self.state.lock().unwrap();
+        let prefix = format!("{}/", dir);
+
+        Ok(state
+            .blobs
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(String::from)
+            .collect())
+    }
+
+    async fn read(&self, path: &str) -> Result<Option<(Vec<u8>, String)>, Box<dyn Error + Send + Sync>> {
+        Ok(self.blob(path).map(|content| {
+            let revision = revision_of(&content);
+            (content, revision)
+        }))
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        content: &[u8],
+        revision: Option<&str>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+
+        let current = state.blobs.get(path).map(|content| revision_of(content));
+
+        if current.as_deref() != revision {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        state.blobs.insert(path.to_string(), content.to_vec());
+
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str, revision: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+
+        let current = state.blobs.get(path).map(|content| revision_of(content));
+
+        if current.as_deref() != Some(revision) {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        state.blobs.remove(path);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trans_content::TransHead;
+
+    const BLOB: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    fn entry(id: &str) -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: id.to_string(),
+                ..Default::default()
+            },
+            body: "/ping".to_string(),
+            payload: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn mailbox_writes_are_guarded_by_the_revision() {
+        let backend = MemoryBackend::new();
+
+        let (content, revision) = backend.read_mailbox().await.unwrap();
+        assert!(content.is_empty());
+
+        backend.write_mailbox(&[entry("a")], &revision).await.unwrap();
+
+        // 用旧的版本写入会冲突，数据不变
+        let err = backend.write_mailbox(&[entry("b")], &revision).await.unwrap_err();
+        assert!(err.is::<RevisionConflict>());
+
+        let (content, _) = backend.read_mailbox().await.unwrap();
+        assert_eq!(content.len(), 1);
+        assert_eq!(content[0].head.id, "a");
+        assert_eq!(backend.raw_mailbox(), serde_json::to_string(&[entry("a")]).unwrap());
+    }
+
+    #[tokio::test]
+    async fn clones_share_the_storage() {
+        let backend = MemoryBackend::new();
+        let other = backend.clone();
+
+        backend.put_blob(BLOB, b"data").await.unwrap();
+
+        assert_eq!(other.get_blob(BLOB).await.unwrap(), b"data".to_vec());
+        assert_eq!(other.blob_names(), vec![BLOB.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn blobs_are_written_read_and_deleted() {
+        let backend = MemoryBackend::new();
+
+        backend.put_blob(BLOB, b"first").await.unwrap();
+        backend.put_blob(BLOB, b"second").await.unwrap();
+        assert_eq!(backend.get_blob(BLOB).await.unwrap(), b"second".to_vec());
+
+        backend.delete_blob(BLOB).await.unwrap();
+        // 找不到的文件是 NotFound，不会被当作网络错误重试
+        let err = BtpError::from(backend.get_blob(BLOB).await.unwrap_err());
+        assert!(matches!(err, BtpError::NotFound(_)));
+        let err = BtpError::from(backend.delete_blob(BLOB).await.unwrap_err());
+        assert!(matches!(err, BtpError::NotFound(_)));
+    }
+
+    #[tokio::test]
+    async fn only_generated_blob_names_are_deleted() {
+        let backend = MemoryBackend::new();
+
+        backend.put_blob("notes", b"keep").await.unwrap();
+
+        let err = BtpError::from(backend.delete_blob("notes").await.unwrap_err());
+        assert!(matches!(err, BtpError::InvalidInput(_)));
+        assert!(!err.is_transient());
+        assert_eq!(backend.blob("notes"), Some(b"keep".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn shard_files_are_guarded_by_their_revision() {
+        let backend = MemoryBackend::new();
+
+        backend.write("requests/a.json", b"a", None).await.unwrap();
+        backend.write("requests/b.json", b"b", None).await.unwrap();
+        backend.write("requests/old/c.json", b"c", None).await.unwrap();
+
+        // 已存在的文件不能再新建
+        let err = backend.write("requests/a.json", b"a2", None).await.unwrap_err();
+        assert!(err.is::<RevisionConflict>());
+
+        let (content, revision) = backend.read("requests/a.json").await.unwrap().unwrap();
+        assert_eq!(content, b"a".to_vec());
+
+        backend
+            .write("requests/a.json", b"a2", Some(&revision))
+            .await
+            .unwrap();
+
+        // 版本过期之后不能再删除
+        let err = backend.delete("requests/a.json", &revision).await.unwrap_err();
+        assert!(err.is::<RevisionConflict>());
+
+        let (_, revision) = backend.read("requests/a.json").await.unwrap().unwrap();
+        backend.delete("requests/a.json", &revision).await.unwrap();
+        assert_eq!(backend.read("requests/a.json").await.unwrap(), None);
+
+        // 只列出目录下直接包含的文件
+        assert_eq!(backend.list("requests").await.unwrap(), vec!["b.json".to_string()]);
+        assert!(backend.list("responses").await.unwrap().is_empty());
+    }
+}
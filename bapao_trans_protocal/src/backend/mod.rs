@@ -0,0 +1,315 @@
+mod dir;
+mod gitee;
+mod memory;
+mod sharded;
+
+pub use dir::DirBackend;
+pub(crate) use dir::write_atomic;
+pub use gitee::GiteeBackend;
+pub use memory::MemoryBackend;
+pub use sharded::{ShardStore, ShardedBackend};
+
+use async_trait::async_trait;
+use sha1::{Digest, Sha1};
+use std::{collections::HashMap, error::Error, fmt, time::Duration};
+use uuid::Uuid;
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::{self, BtpError};
+use crate::retry::RateBudget;
+use crate::trans_content::ReqContent;
+
+/// Storage backend that carries the request/response mailbox.
+///
+/// The transport layer only needs a handful of primitives from the place where
+/// clients and the listener meet: read the mailbox together with its current
+/// revision, write the mailbox back guarded by that revision, and upload,
+/// download or remove the binary blobs referenced by file responses.
+///
+/// `GiteeBackend` is the default implementation and talks to the Gitee contents
+/// API. `MemoryBackend` and `DirBackend` run without the network, and other
+/// implementations can be plugged into `BtpListener::with_backend` to use
+/// another transport. Each of these keeps the mailbox in a single file;
+/// `ShardedBackend` wraps any of them to store one file per request instead.
+///
+/// # Revisions
+///
+/// The revision returned by `read_mailbox` is opaque to the caller (for Gitee it
+/// is the blob `sha` of the mailbox file). It must be passed back unchanged to
+/// `write_mailbox`, which should refuse the write with `RevisionConflict` (or
+/// `BtpError::Conflict`) when the mailbox has changed in the meantime.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use bapao_trans_protocal::{backend::{GiteeBackend, MailboxBackend}, BtpListener};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let backend = GiteeBackend::new();
+///     let (entries, revision) = backend.read_mailbox().await.unwrap();
+///     println!("{} entries at revision {}", entries.len(), revision);
+///
+///     let mut listener = BtpListener::with_backend(backend);
+///     let _requests = listener.accept().await.unwrap();
+/// }
+/// ```
+#[async_trait]
+pub trait MailboxBackend: Send + Sync {
+    /// Reads every entry of the mailbox and the revision it was read at.
+    async fn read_mailbox(&self) -> Result<(Vec<ReqContent>, String), Box<dyn Error + Send + Sync>>;
+
+    /// Replaces the mailbox content, provided it is still at `revision`.
+    async fn write_mailbox(
+        &self,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Stores a binary blob under `name` next to the mailbox.
+    async fn put_blob(&self, name: &str, content: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Reads the blob stored under `name`.
+    async fn get_blob(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>>;
+
+    /// Removes the blob stored under `name`.
+    ///
+    /// Implementations refuse names the listener does not generate, see
+    /// `is_blob_name`, so a forged response cannot get other files deleted.
+    async fn delete_blob(&self, name: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Stores `blobs` and replaces the mailbox content in one go, provided the
+    /// mailbox is still at `revision`.
+    ///
+    /// Blobs that have been stored are removed from `blobs`, so a commit that
+    /// failed halfway only re-sends the missing ones when it is retried.
+    ///
+    /// The default implementation stores the blobs one by one with `put_blob`
+    /// and then calls `write_mailbox`; the mailbox is never written before all
+    /// blobs it may reference exist. Backends that can write several files
+    /// atomically override this so that everything lands together or not at all.
+    async fn commit(
+        &self,
+        blobs: &mut HashMap<String, Vec<u8>>,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        commit_one_by_one(self, blobs, content, revision).await
+    }
+
+    /// Returns the API calls left according to the last response, for
+    /// backends whose host limits the number of calls; `None` by default.
+    ///
+    /// `AppListener::listen` slows down polling once the budget runs low, see
+    /// `RateBudget::pacing`.
+    fn rate_budget(&self) -> Option<RateBudget> {
+        None
+    }
+}
+
+/// Error returned by `write_mailbox` when the mailbox is no longer at the
+/// revision the caller read it at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RevisionConflict;
+
+impl fmt::Display for RevisionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mailbox revision conflict")
+    }
+}
+
+impl Error for RevisionConflict {}
+
+/// How often `update_mailbox` re-reads and re-applies an update after a
+/// `RevisionConflict` before giving up.
+const CONFLICT_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled after every further conflict.
+const CONFLICT_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Applies `apply` to the current mailbox and writes the result back, retrying
+/// when someone else wrote the mailbox in between.
+///
+/// The mailbox is shared by the listener and any number of clients, so a write
+/// can always race with another one. Instead of overwriting the other side's
+/// entries, `update_mailbox` re-reads the mailbox after a `RevisionConflict`,
+/// runs `apply` again on the fresh entries and retries with exponential backoff.
+/// `apply` must therefore derive its result from the entries it is given and
+/// only re-apply its own changes, keyed by request id.
+///
+/// # Parameters
+///
+/// * `backend` - The mailbox backend to update
+/// * `apply` - Receives the current entries and returns the entries to write
+///   (`None` to leave the mailbox untouched) together with a value handed back
+///   to the caller
+///
+/// # Returns
+///
+/// The value returned by the `apply` call whose result was written.
+///
+/// # Errors
+///
+/// Fails with the first error that is not a `RevisionConflict`, with the error
+/// returned by `apply`, or with `RevisionConflict` once every attempt conflicted.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::backend::{self, MemoryBackend};
+/// use bapao_trans_protocal::trans_content::{ReqContent, TransHead};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let backend = MemoryBackend::new();
+///
+///     let count = backend::update_mailbox(&backend, |mut content| {
+///         content.push(ReqContent {
+///             head: TransHead {
+///                 id: "req_123".to_string(),
+///                 ..Default::default()
+///             },
+///             body: "/api/status".to_string(),
+///             payload: None,
+///         });
+///         let count = content.len();
+///
+///         Ok((Some(content), count))
+///     })
+///     .await
+///     .unwrap();
+///
+///     assert_eq!(count, 1);
+/// }
+/// ```
+pub async fn update_mailbox<B, F, T>(backend: &B, apply: F) -> Result<T, Box<dyn Error + Send + Sync>>
+where
+    B: MailboxBackend + ?Sized,
+    F: FnMut(Vec<ReqContent>) -> Result<(Option<Vec<ReqContent>>, T), Box<dyn Error + Send + Sync>>,
+{
+    update_mailbox_with_clock(backend, &SystemClock, apply).await
+}
+
+/// Same as `update_mailbox`, waiting between conflicting attempts with
+/// `clock` instead of the system clock.
+pub async fn update_mailbox_with_clock<B, F, T>(
+    backend: &B,
+    clock: &dyn Clock,
+    apply: F,
+) -> Result<T, Box<dyn Error + Send + Sync>>
+where
+    B: MailboxBackend + ?Sized,
+    F: FnMut(Vec<ReqContent>) -> Result<(Option<Vec<ReqContent>>, T), Box<dyn Error + Send + Sync>>,
+{
+    commit_mailbox(backend, clock, &mut HashMap::new(), apply).await
+}
+
+/// Same as `update_mailbox_with_clock`, additionally storing `blobs` together
+/// with the mailbox through `MailboxBackend::commit`.
+///
+/// Blobs are removed from `blobs` once they are stored. They are committed only
+/// when `apply` returns entries to write.
+pub async fn commit_mailbox<B, F, T>(
+    backend: &B,
+    clock: &dyn Clock,
+    blobs: &mut HashMap<String, Vec<u8>>,
+    mut apply: F,
+) -> Result<T, Box<dyn Error + Send + Sync>>
+where
+    B: MailboxBackend + ?Sized,
+    F: FnMut(Vec<ReqContent>) -> Result<(Option<Vec<ReqContent>>, T), Box<dyn Error + Send + Sync>>,
+{
+    let mut backoff = CONFLICT_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        let (content, revision) = backend.read_mailbox().await?;
+
+        let (content, value) = match apply(content)? {
+            (Some(content), value) => (content, value),
+            (None, value) => return Ok(value),
+        };
+
+        match backend.commit(blobs, &content, &revision).await {
+            Ok(()) => return Ok(value),
+            Err(err) if error::is_conflict(err.as_ref()) && attempt < CONFLICT_ATTEMPTS => {
+                // mailbox 在读取之后被修改过，重新读取后再合并一次
+                println!("mailbox 已被修改，{:?} 后重试（第 {} 次）", backoff, attempt);
+
+                clock.sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Computes the revision of stored content the way git names blobs, so local
+/// backends report the same kind of `sha` as Gitee does.
+pub(crate) fn revision_of(content: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content);
+
+    format!("{:x}", hasher.finalize())
+}
+
+/// Returns whether `name` is a blob name the listener generates: a hyphenated
+/// uuid, optionally followed by `.<n>` for the chunks of a large file.
+///
+/// Blob names are read back from the mailbox, which everyone with write access
+/// to it can edit, so they are checked before a blob is deleted.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::backend::is_blob_name;
+///
+/// assert!(is_blob_name("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+/// assert!(is_blob_name("67e55044-10b1-426f-9247-bb680e5fe0c8.3"));
+/// assert!(!is_blob_name("io"));
+/// assert!(!is_blob_name("67e55044-10b1-426f-9247-bb680e5fe0c8.md"));
+/// ```
+pub fn is_blob_name(name: &str) -> bool {
+    let (uuid, index) = match name.split_once('.') {
+        Some((uuid, index)) => (uuid, Some(index)),
+        None => (name, None),
+    };
+
+    // 分块的序号只能是数字
+    let index_valid = match index {
+        Some(index) => !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()),
+        None => true,
+    };
+
+    index_valid && uuid.len() == 36 && Uuid::parse_str(uuid).is_ok()
+}
+
+/// `MailboxBackend::commit` 的默认实现：逐个上传文件，最后写入 mailbox，
+/// 不能原子提交的后端也可以直接调用
+pub(crate) async fn commit_one_by_one<B: MailboxBackend + ?Sized>(
+    backend: &B,
+    blobs: &mut HashMap<String, Vec<u8>>,
+    content: &[ReqContent],
+    revision: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let names: Vec<String> = blobs.keys().cloned().collect();
+
+    for name in names.iter() {
+        backend.put_blob(name, &blobs[name]).await?;
+        blobs.remove(name);
+    }
+
+    backend.write_mailbox(content, revision).await
+}
+
+/// 删除 blob 之前检查名称，只删除监听端生成的文件
+pub(crate) fn check_blob_name(name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !is_blob_name(name) {
+        return Err(BtpError::InvalidInput(format!("invalid blob name: {}", name)).into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,507 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+
+use super::{check_blob_name, revision_of, MailboxBackend};
+use crate::error::BtpError;
+use crate::retry::RateBudget;
+use crate::trans_content::{self, ReqContent, State};
+
+const REQUESTS_DIR: &str = "requests";
+const RESPONSES_DIR: &str = "responses";
+const FILES_DIR: &str = "files";
+
+/// File storage underneath a `ShardedBackend`.
+///
+/// Paths are `/`-separated and relative to the root of the store, e.g.
+/// `requests/<id>.json`. Every file has a revision (the git blob `sha` for
+/// Gitee), and writes and deletes are guarded by the revision the caller read
+/// the file at, exactly like `MailboxBackend::write_mailbox` guards the whole
+/// mailbox.
+///
+/// `GiteeBackend`, `DirBackend` and `MemoryBackend` implement this trait, so
+/// each of them can be used with either layout.
+#[async_trait]
+pub trait ShardStore: Send + Sync {
+    /// Lists the names of the files directly inside `dir`. A directory that
+    /// does not exist is empty.
+    async fn list(&self, dir: &str) -> Result<Vec<String>, Box<dyn Error + Send + Sync>>;
+
+    /// Reads the file at `path` together with its revision, `None` when it does not exist.
+    async fn read(
+        &self,
+        path: &str,
+    ) -> Result<Option<(Vec<u8>, String)>, Box<dyn Error + Send + Sync>>;
+
+    /// Writes the file at `path`, provided it is still at `revision`.
+    ///
+    /// `revision` is `None` to create a file that must not exist yet. Fails
+    /// with `RevisionConflict` (or `BtpError::Conflict`) when the file has
+    /// changed in the meantime.
+    async fn write(
+        &self,
+        path: &str,
+        content: &[u8],
+        revision: Option<&str>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Deletes the file at `path`, provided it is still at `revision`.
+    async fn delete(&self, path: &str, revision: &str) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Returns the API calls left, see `MailboxBackend::rate_budget`.
+    fn rate_budget(&self) -> Option<RateBudget> {
+        None
+    }
+}
+
+/// 读取时每个文件的版本，以及文件中数据的摘要（用来判断数据是否有变化）
+#[derive(Serialize, Deserialize, Debug)]
+struct ShardRevision {
+    revision: String,
+    digest: String,
+}
+
+/// Mailbox backend storing one file per request instead of a single mailbox file.
+///
+/// Clients write their requests to `requests/<id>.json`, the listener writes
+/// its updates (`Processing`, responses, `Expired`) to `responses/<id>.json`,
+/// and file responses are stored as `files/<name>`. The mailbox is read by
+/// listing both directories; when both files of a request exist, the one in
+/// `responses` wins.
+///
+/// Since the listener and the clients write different files, they no longer
+/// contend on a single blob, and only the entries that actually changed are
+/// written back. Both sides touch the same file only to remove a finished
+/// request, and such a write is still guarded by the file's revision.
+///
+/// The single-file layout stays the default; wrap any `ShardStore` in a
+/// `ShardedBackend` to use this one. Listener and clients must use the same layout.
+///
+/// # Revisions
+///
+/// The revision returned by `read_mailbox` records the revision of every file
+/// that was read, so `write_mailbox` can tell a request it removed from one
+/// that was created after the read.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::backend::{MemoryBackend, ShardedBackend};
+/// use bapao_trans_protocal::BtpListener;
+///
+/// let backend = ShardedBackend::new(MemoryBackend::new());
+/// let listener = BtpListener::with_backend(backend);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ShardedBackend<S> {
+    store: S,
+}
+
+impl<S> ShardedBackend<S>
+where
+    S: ShardStore,
+{
+    /// Creates a backend storing one file per request in `store`.
+    pub fn new(store: S) -> Self {
+        ShardedBackend { store }
+    }
+
+    /// Returns the store the files are kept in.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+/// 请求 id 和 blob 名称都会作为文件名使用，只能是单层的普通文件名
+fn check_name(name: &str) -> Result<&str, Box<dyn Error + Send + Sync>> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') || name.contains('\\') {
+        return Err(BtpError::InvalidInput(format!("invalid file name: {}", name)).into());
+    }
+
+    Ok(name)
+}
+
+/// 客户端写入的状态放在 requests 目录，监听端写入的状态放在 responses 目录
+fn entry_path(entry: &ReqContent) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let dir = match entry.head.state {
+        State::Pending | State::Cancelled => REQUESTS_DIR,
+        State::Processing | State::Done | State::Error | State::Expired => RESPONSES_DIR,
+    };
+
+    Ok(format!("{}/{}.json", dir, check_name(&entry.head.id)?))
+}
+
+fn entry_id(path: &str) -> &str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+
+    name.strip_suffix(".json").unwrap_or(name)
+}
+
+/// 按照 JSON 值计算摘要，字段顺序不同的同一份数据摘要相同
+fn digest_of(entry: &ReqContent) -> Result<String, Box<dyn Error + Send + Sync>> {
+    Ok(revision_of(
+        serde_json::to_value(entry)?.to_string().as_bytes(),
+    ))
+}
+
+#[async_trait]
+impl<S> MailboxBackend for ShardedBackend<S>
+where
+    S: ShardStore,
+{
+    async fn read_mailbox(
+        &self,
+    ) -> Result<(Vec<ReqContent>, String), Box<dyn Error + Send + Sync>> {
+        let mut snapshot: BTreeMap<String, ShardRevision> = BTreeMap::new();
+        let mut entries: HashMap<String, ReqContent> = HashMap::new();
+
+        // 先读 requests 再读 responses，同一个请求以 responses 中的数据为准
+        for dir in [REQUESTS_DIR, RESPONSES_DIR] {
+            for name in self.store.list(dir).await? {
+                let id = match name.strip_suffix(".json") {
+                    Some(id) => id.to_string(),
+                    None => continue,
+                };
+
+                let path = format!("{}/{}", dir, name);
+
+                // 列目录之后文件可能已经被删除了
+                let (bytes, revision) = match self.store.read(&path).await? {
+                    Some(file) => file,
+                    None => continue,
+                };
+
+                let entry = serde_json::from_slice(&bytes)
+                    .ok()
+                    .and_then(trans_content::decode_entry);
+
+                // 无效的文件也记录下来，下次写入时会被清理掉
+                let digest = match entry {
+                    Some(mut entry) => {
+                        // 以文件名中的 id 为准
+                        entry.head.id = id.clone();
+
+                        let digest = digest_of(&entry)?;
+                        entries.insert(id, entry);
+                        digest
+                    }
+                    None => String::new(),
+                };
+
+                snapshot.insert(path, ShardRevision { revision, digest });
+            }
+        }
+
+        let mut content: Vec<ReqContent> = entries.into_values().collect();
+        content.sort_by(|a, b| (a.head.timestamp, &a.head.id).cmp(&(b.head.timestamp, &b.head.id)));
+
+        Ok((content, serde_json::to_string(&snapshot)?))
+    }
+
+    async fn write_mailbox(
+        &self,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let snapshot: BTreeMap<String, ShardRevision> = if revision.is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_json::from_str(revision)?
+        };
+
+        let mut ids = HashSet::new();
+
+        for entry in content.iter() {
+            ids.insert(&entry.head.id[..]);
+
+            let path = entry_path(entry)?;
+            let digest = digest_of(entry)?;
+            let current = snapshot.get(&path);
+
+            // 只写入有变化的数据
+            if current.map(|file| &file.digest) == Some(&digest) {
+                continue;
+            }
+
+            self.store
+                .write(
+                    &path,
+                    &serde_json::to_vec(entry)?,
+                    current.map(|file| &file.revision[..]),
+                )
+                .await?;
+        }
+
+        // 不在新数据中的请求，删除它的所有文件
+        for (path, file) in snapshot.iter() {
+            if !ids.contains(entry_id(path)) {
+                self.store.delete(path, &file.revision).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn put_blob(
+        &self,
+        name: &str,
+        content: &[u8],
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = format!("{}/{}", FILES_DIR, check_name(name)?);
+
+        let revision = self.store.read(&path).await?.map(|(_, revision)| revision);
+
+        self.store.write(&path, content, revision.as_deref()).await
+    }
+
+    async fn get_blob(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let path = format!("{}/{}", FILES_DIR, check_name(name)?);
+
+        match self.store.read(&path).await? {
+            Some((content, _)) => Ok(content),
+            None => Err(BtpError::NotFound(format!("blob {}", name)).into()),
+        }
+    }
+
+    async fn delete_blob(&self, name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        check_blob_name(name)?;
+
+        let path = format!("{}/{}", FILES_DIR, check_name(name)?);
+
+        match self.store.read(&path).await? {
+            Some((_, revision)) => self.store.delete(&path, &revision).await,
+            None => Err(BtpError::NotFound(format!("blob {}", name)).into()),
+        }
+    }
+
+    fn rate_budget(&self) -> Option<RateBudget> {
+        self.store.rate_budget()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{self, MemoryBackend, RevisionConflict};
+    use crate::trans_content::TransHead;
+
+    const BLOB: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    fn entry(id: &str, state: State, timestamp: i64) -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: id.to_string(),
+                state,
+                timestamp,
+                ..Default::default()
+            },
+            body: "/ping".to_string(),
+            payload: None,
+        }
+    }
+
+    fn sharded() -> (ShardedBackend<MemoryBackend>, MemoryBackend) {
+        let store = MemoryBackend::new();
+
+        (ShardedBackend::new(store.clone()), store)
+    }
+
+    async fn files(store: &MemoryBackend, dir: &str) -> Vec<String> {
+        let mut names = store.list(dir).await.unwrap();
+        names.sort();
+        names
+    }
+
+    async fn states(backend: &ShardedBackend<MemoryBackend>) -> Vec<(String, State)> {
+        let (content, _) = backend.read_mailbox().await.unwrap();
+
+        content
+            .into_iter()
+            .map(|item| (item.head.id, item.head.state))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn entries_are_written_by_state() {
+        let (backend, store) = sharded();
+
+        let (_, revision) = backend.read_mailbox().await.unwrap();
+        backend
+            .write_mailbox(
+                &[entry("a", State::Pending, 1), entry("b", State::Pending, 2)],
+                &revision,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(files(&store, REQUESTS_DIR).await, vec!["a.json", "b.json"]);
+        assert!(files(&store, RESPONSES_DIR).await.is_empty());
+
+        // 监听端的更新写到 responses，requests 中的文件不变
+        let (_, revision) = backend.read_mailbox().await.unwrap();
+        backend
+            .write_mailbox(
+                &[
+                    entry("a", State::Processing, 1),
+                    entry("b", State::Pending, 2),
+                ],
+                &revision,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(files(&store, REQUESTS_DIR).await, vec!["a.json", "b.json"]);
+        assert_eq!(files(&store, RESPONSES_DIR).await, vec!["a.json"]);
+        assert_eq!(
+            states(&backend).await,
+            vec![
+                ("a".to_string(), State::Processing),
+                ("b".to_string(), State::Pending),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_entries_lose_all_their_files() {
+        let (backend, store) = sharded();
+
+        backend::update_mailbox(&backend, |_| {
+            Ok((Some(vec![entry("a", State::Pending, 1)]), ()))
+        })
+        .await
+        .unwrap();
+        backend::update_mailbox(&backend, |_| {
+            Ok((Some(vec![entry("a", State::Done, 1)]), ()))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(files(&store, REQUESTS_DIR).await, vec!["a.json"]);
+        assert_eq!(files(&store, RESPONSES_DIR).await, vec!["a.json"]);
+
+        backend::update_mailbox(&backend, |_| Ok((Some(vec![]), ())))
+            .await
+            .unwrap();
+
+        assert!(files(&store, REQUESTS_DIR).await.is_empty());
+        assert!(files(&store, RESPONSES_DIR).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_created_after_the_read_are_kept() {
+        let (backend, _) = sharded();
+
+        let (_, listener_revision) = backend.read_mailbox().await.unwrap();
+
+        // 读取之后客户端又写入了一个请求
+        backend::update_mailbox(&backend, |mut content| {
+            content.push(entry("late", State::Pending, 2));
+            Ok((Some(content), ()))
+        })
+        .await
+        .unwrap();
+
+        backend
+            .write_mailbox(&[entry("mine", State::Pending, 1)], &listener_revision)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            states(&backend).await,
+            vec![
+                ("mine".to_string(), State::Pending),
+                ("late".to_string(), State::Pending),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn changed_files_conflict_with_a_stale_snapshot() {
+        let (backend, _) = sharded();
+
+        backend::update_mailbox(&backend, |_| {
+            Ok((Some(vec![entry("a", State::Pending, 1)]), ()))
+        })
+        .await
+        .unwrap();
+
+        let (_, stale) = backend.read_mailbox().await.unwrap();
+
+        // 客户端取消了请求
+        backend::update_mailbox(&backend, |_| {
+            Ok((Some(vec![entry("a", State::Cancelled, 1)]), ()))
+        })
+        .await
+        .unwrap();
+
+        // 没有变化的数据不会写入，所以不会覆盖客户端的修改
+        backend
+            .write_mailbox(&[entry("a", State::Pending, 1)], &stale)
+            .await
+            .unwrap();
+        assert_eq!(
+            states(&backend).await,
+            vec![("a".to_string(), State::Cancelled)]
+        );
+
+        // 按旧的版本删除被修改过的文件会冲突
+        let err = backend.write_mailbox(&[], &stale).await.unwrap_err();
+        assert!(err.is::<RevisionConflict>());
+        assert_eq!(
+            states(&backend).await,
+            vec![("a".to_string(), State::Cancelled)]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_files_are_cleaned_up() {
+        let (backend, store) = sharded();
+
+        store
+            .write("requests/broken.json", b"not json", None)
+            .await
+            .unwrap();
+        store
+            .write("requests/notes.txt", b"ignored", None)
+            .await
+            .unwrap();
+
+        let (content, revision) = backend.read_mailbox().await.unwrap();
+        assert!(content.is_empty());
+
+        backend.write_mailbox(&content, &revision).await.unwrap();
+
+        assert_eq!(files(&store, REQUESTS_DIR).await, vec!["notes.txt"]);
+    }
+
+    #[tokio::test]
+    async fn ids_must_be_plain_file_names() {
+        let (backend, _) = sharded();
+        let (_, revision) = backend.read_mailbox().await.unwrap();
+
+        for id in ["../io", "a/b", ".hidden", ""] {
+            let result = backend
+                .write_mailbox(&[entry(id, State::Pending, 1)], &revision)
+                .await;
+
+            assert!(result.is_err(), "{}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn blobs_are_stored_under_files() {
+        let (backend, store) = sharded();
+
+        backend.put_blob(BLOB, b"first").await.unwrap();
+        backend.put_blob(BLOB, b"second").await.unwrap();
+
+        assert_eq!(files(&store, FILES_DIR).await, vec![BLOB]);
+        assert_eq!(backend.get_blob(BLOB).await.unwrap(), b"second".to_vec());
+        assert!(backend.get_blob("../io").await.is_err());
+
+        backend.delete_blob(BLOB).await.unwrap();
+        assert!(files(&store, FILES_DIR).await.is_empty());
+        assert!(backend.delete_blob(BLOB).await.is_err());
+    }
+}
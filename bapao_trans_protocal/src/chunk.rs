@@ -0,0 +1,259 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{error::Error, fmt};
+
+/// Chunk size used for file responses unless configured otherwise (512 KiB).
+///
+/// Chunks are uploaded base64-encoded, so a chunk of this size stays well below
+/// the size Gitee's contents API still returns file bodies for.
+pub const DEFAULT_CHUNK_SIZE: usize = 512 * 1024;
+
+/// Content type of a response whose body is a `ChunkManifest`.
+pub const CHUNKED_FILE: &str = "chunked_file";
+
+/// Describes a file response that was split into several blobs.
+///
+/// Large `TransUnitType::File` responses are stored as chunks named
+/// `<uuid>.<n>`, and the response body is this manifest serialized as JSON
+/// (content type `chunked_file`). The client downloads the chunks in order and
+/// checks the reassembled file against `size` and `sha256`.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::chunk::ChunkManifest;
+///
+/// let data = vec![7u8; 10];
+/// let (manifest, chunks) = ChunkManifest::split("shot", &data, 4);
+///
+/// assert_eq!(manifest.chunks, vec!["shot.0", "shot.1", "shot.2"]);
+///
+/// let parts = chunks.into_iter().map(|(_, chunk)| chunk).collect();
+/// assert_eq!(manifest.assemble(parts).unwrap(), data);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkManifest {
+    /// Size of the whole file in bytes
+    pub size: u64,
+    /// Size of every chunk but the last one
+    pub chunk_size: u64,
+    /// Blob names of the chunks, in order
+    pub chunks: Vec<String>,
+    /// Hex-encoded SHA-256 of the whole file
+    pub sha256: String,
+}
+
+impl ChunkManifest {
+    /// Splits `data` into chunks of `chunk_size` bytes named `<name>.<n>`.
+    ///
+    /// # Returns
+    ///
+    /// The manifest and the chunks to upload, as `(blob name, content)` pairs.
+    pub fn split(
+        name: &str,
+        data: &[u8],
+        chunk_size: usize,
+    ) -> (ChunkManifest, Vec<(String, Vec<u8>)>) {
+        let chunk_size = chunk_size.max(1);
+
+        let chunks: Vec<(String, Vec<u8>)> = data
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| (format!("{}.{}", name, index), chunk.to_vec()))
+            .collect();
+
+        let manifest = ChunkManifest {
+            size: data.len() as u64,
+            chunk_size: chunk_size as u64,
+            chunks: chunks.iter().map(|(name, _)| name.clone()).collect(),
+            sha256: sha256_of(data),
+        };
+
+        (manifest, chunks)
+    }
+
+    /// Checks that `size`, `chunk_size` and the number of chunks fit together.
+    ///
+    /// The manifest is written by whoever can write the mailbox, so it is
+    /// checked before any chunk is downloaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChunkMismatch` when the file would not split into exactly the
+    /// listed number of chunks.
+    pub fn validate(&self) -> Result<(), ChunkMismatch> {
+        if self.chunk_size == 0 {
+            return Err(ChunkMismatch(String::from("chunk size is zero")));
+        }
+
+        // 分块数量必须等于 size / chunk_size 向上取整
+        let expected = self.size.div_ceil(self.chunk_size);
+
+        if expected != self.chunks.len() as u64 {
+            return Err(ChunkMismatch(format!(
+                "{} bytes in chunks of {} bytes make {} chunks, manifest lists {}",
+                self.size,
+                self.chunk_size,
+                expected,
+                self.chunks.len()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Joins the downloaded `chunks`, given in manifest order, and verifies the result.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChunkMismatch` when the manifest is inconsistent (see
+    /// `validate`), or when the number or sizes of the chunks, the total size
+    /// or the SHA-256 do not match the manifest.
+    pub fn assemble(&self, chunks: Vec<Vec<u8>>) -> Result<Vec<u8>, ChunkMismatch> {
+        self.validate()?;
+
+        if chunks.len() != self.chunks.len() {
+            return Err(ChunkMismatch(format!(
+                "expected {} chunks, got {}",
+                self.chunks.len(),
+                chunks.len()
+            )));
+        }
+
+        // 按实际下载的大小分配内存，不信任清单中的 size
+        let total: usize = chunks.iter().map(Vec::len).sum();
+
+        if total as u64 != self.size {
+            return Err(ChunkMismatch(format!(
+                "file has {} bytes, expected {}",
+                total, self.size
+            )));
+        }
+
+        let mut data = Vec::with_capacity(total);
+
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            // 除最后一块外，每块的大小都应该等于 chunk_size
+            if index + 1 < self.chunks.len() && chunk.len() as u64 != self.chunk_size {
+                return Err(ChunkMismatch(format!(
+                    "chunk {} has {} bytes, expected {}",
+                    self.chunks[index],
+                    chunk.len(),
+                    self.chunk_size
+                )));
+            }
+
+            data.extend(chunk);
+        }
+
+        if sha256_of(&data) != self.sha256 {
+            return Err(ChunkMismatch(String::from("sha256 does not match")));
+        }
+
+        Ok(data)
+    }
+}
+
+/// Error returned by `ChunkManifest::assemble` when the chunks do not add up
+/// to the file described by the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkMismatch(pub String);
+
+impl fmt::Display for ChunkMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chunked file failed integrity check: {}", self.0)
+    }
+}
+
+impl Error for ChunkMismatch {}
+
+fn sha256_of(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(data: &[u8]) -> (ChunkManifest, Vec<Vec<u8>>) {
+        let (manifest, chunks) = ChunkManifest::split("file", data, 4);
+
+        (manifest, chunks.into_iter().map(|(_, chunk)| chunk).collect())
+    }
+
+    #[test]
+    fn split_and_assemble_round_trip() {
+        let data: Vec<u8> = (0..10).collect();
+        let (manifest, chunks) = split(&data);
+
+        assert_eq!(manifest.size, 10);
+        assert_eq!(manifest.chunks, vec!["file.0", "file.1", "file.2"]);
+        assert_eq!(chunks[2], vec![8, 9]);
+        assert_eq!(manifest.assemble(chunks).unwrap(), data);
+    }
+
+    #[test]
+    fn empty_file_has_no_chunks() {
+        let (manifest, chunks) = split(&[]);
+
+        assert!(manifest.chunks.is_empty());
+        assert_eq!(manifest.assemble(chunks).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn missing_chunk_is_rejected() {
+        let (manifest, mut chunks) = split(&[1; 10]);
+        chunks.pop();
+
+        assert!(manifest.assemble(chunks).is_err());
+    }
+
+    #[test]
+    fn wrong_chunk_count_is_rejected() {
+        let (mut manifest, chunks) = split(&[1; 10]);
+        manifest.chunks.push(String::from("file.3"));
+
+        assert!(manifest.validate().is_err());
+        assert!(manifest.assemble(chunks).is_err());
+    }
+
+    #[test]
+    fn hash_mismatch_is_rejected() {
+        let (manifest, mut chunks) = split(&[1; 10]);
+        chunks[1][0] = 2;
+
+        let err = manifest.assemble(chunks).unwrap_err();
+        assert_eq!(err.0, "sha256 does not match");
+    }
+
+    #[test]
+    fn size_mismatch_is_rejected() {
+        let (manifest, mut chunks) = split(&[1; 10]);
+        chunks[2].push(1);
+
+        assert!(manifest.assemble(chunks).is_err());
+
+        // 中间的块大小不对
+        let (manifest, mut chunks) = split(&[1; 10]);
+        chunks[0].pop();
+        chunks[2].push(1);
+
+        assert!(manifest.assemble(chunks).is_err());
+    }
+
+    #[test]
+    fn oversized_manifest_is_rejected_before_allocating() {
+        let (mut manifest, chunks) = split(&[1; 10]);
+        manifest.size = u64::MAX;
+
+        assert!(manifest.validate().is_err());
+        assert!(manifest.assemble(chunks.clone()).is_err());
+
+        // 数量对得上，但 chunk_size 远大于实际下载的数据
+        manifest.chunk_size = u64::MAX / 2 + 1;
+        manifest.chunks.truncate(2);
+
+        assert!(manifest.validate().is_ok());
+        assert!(manifest.assemble(chunks[..2].to_vec()).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Local, Utc};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// Source of the current time, and of the waits between retries and polls.
+///
+/// Everything that depends on time (request expiry, response timestamps,
+/// replay checks, retry backoff, poll scheduling and rate-limit pacing) reads
+/// it through a `Clock` instead of calling `Utc::now()` or sleeping directly.
+/// `SystemClock` is used by default; a `ManualClock` makes the same code run
+/// against a simulated time, see `BtpListener::set_clock`.
+#[async_trait]
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Returns the current time in the local time zone, used for daily
+    /// windows such as quiet hours.
+    fn local_now(&self) -> DateTime<Local> {
+        self.now().with_timezone(&Local)
+    }
+
+    /// Waits for `duration`.
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The real clock: `Utc::now()` and `tokio::time::sleep`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Returns the shared `SystemClock`, the default of every component.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[derive(Debug)]
+struct ManualState {
+    now: DateTime<Utc>,
+    slept: Vec<Duration>,
+}
+
+/// A clock that only moves when told to, for deterministic tests.
+///
+/// `sleep` returns immediately after advancing the clock by the requested
+/// duration, so backoff and poll loops run through hours of simulated time
+/// without waiting. Every requested sleep is recorded and can be inspected
+/// with `slept`. Clones share the same time.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::clock::{Clock, ManualClock};
+/// use chrono::{TimeZone, Utc};
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let clock = ManualClock::new(Utc.ymd(2024, 1, 1).and_hms(12, 0, 0));
+///
+///     clock.advance(Duration::from_secs(60));
+///     clock.sleep(Duration::from_secs(30)).await;
+///
+///     assert_eq!(clock.now(), Utc.ymd(2024, 1, 1).and_hms(12, 1, 30));
+///     assert_eq!(clock.slept(), vec![Duration::from_secs(30)]);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    state: Arc<Mutex<ManualState>>,
+}
+
+impl ManualClock {
+    /// Creates a clock standing at `start`.
+    pub fn new(start: DateTime<Utc>) -> Self {
+        ManualClock {
+            state: Arc::new(Mutex::new(ManualState {
+                now: start,
+                slept: vec![],
+            })),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        state.now = state.now + to_chrono(duration);
+    }
+
+    /// Moves the clock to `now`, which may lie in the past.
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.state.lock().unwrap().now = now;
+    }
+
+    /// Returns every duration `sleep` was called with, oldest first.
+    pub fn slept(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().slept.clone()
+    }
+}
+
+#[async_trait]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.state.lock().unwrap().now
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        state.now = state.now + to_chrono(duration);
+        state.slept.push(duration);
+    }
+}
+
+/// 超出 chrono 范围的时长按最大值处理
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
@@ -0,0 +1,455 @@
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{error::Error, fmt};
+use x25519_dalek::StaticSecret;
+
+use crate::config::BapaoConfig;
+use crate::trans_content::{CipherHead, ReqContent, TransHead};
+
+/// Name of the only supported scheme, recorded in `CipherHead::alg`.
+pub const ALGORITHM: &str = "x25519-chacha20poly1305";
+
+const HKDF_INFO: &[u8] = b"bapao e2e v1";
+const NONCE_LEN: usize = 12;
+
+/// Error returned when a key cannot be parsed or a payload cannot be decrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CryptoError(pub String);
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "encryption error: {}", self.0)
+    }
+}
+
+impl Error for CryptoError {}
+
+/// X25519 public key, exchanged as base64 text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    /// Parses a base64-encoded 32-byte public key.
+    pub fn from_base64(value: &str) -> Result<Self, CryptoError> {
+        Ok(PublicKey(decode_key(value)?))
+    }
+
+    /// Reads the listener key `agent_public_key` from `config`, `None` when
+    /// the key is not configured.
+    pub fn from_config(config: &BapaoConfig) -> Result<Option<Self>, CryptoError> {
+        config
+            .agent_public_key
+            .as_deref()
+            .map(PublicKey::from_base64)
+            .transpose()
+    }
+
+    /// Returns the key as base64 text.
+    pub fn to_base64(&self) -> String {
+        base64::encode(self.0)
+    }
+}
+
+/// X25519 key pair of a listener or a client.
+///
+/// The listener publishes its public key to its clients, which encrypt their
+/// requests to it. Every client sends its own public key inside the encrypted
+/// request, and the response is encrypted to that key, so only the client that
+/// sent a request can read its response.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::crypto::KeyPair;
+///
+/// let keys = KeyPair::generate();
+/// let restored = KeyPair::from_base64(&keys.secret_base64()).unwrap();
+///
+/// assert_eq!(keys.public_key(), restored.public_key());
+/// ```
+#[derive(Clone)]
+pub struct KeyPair {
+    secret: StaticSecret,
+}
+
+impl KeyPair {
+    /// Generates a new random key pair.
+    pub fn generate() -> Self {
+        KeyPair {
+            secret: StaticSecret::random_from_rng(OsRng),
+        }
+    }
+
+    /// Restores a key pair from its base64-encoded 32-byte secret key.
+    pub fn from_base64(secret: &str) -> Result<Self, CryptoError> {
+        Ok(KeyPair {
+            secret: StaticSecret::from(decode_key(secret)?),
+        })
+    }
+
+    /// Reads the key pair from `secret_key` in `config`, `None` when no secret
+    /// key is configured.
+    pub fn from_config(config: &BapaoConfig) -> Result<Option<Self>, CryptoError> {
+        config
+            .secret_key
+            .as_deref()
+            .map(KeyPair::from_base64)
+            .transpose()
+    }
+
+    /// Returns the secret key as base64 text, to be stored in the config file.
+    pub fn secret_base64(&self) -> String {
+        base64::encode(self.secret.to_bytes())
+    }
+
+    /// Returns the public key belonging to this key pair.
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(x25519_dalek::PublicKey::from(&self.secret).to_bytes())
+    }
+}
+
+impl fmt::Debug for KeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 不输出私钥
+        f.debug_struct("KeyPair")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+/// 请求加密前的原文，回复用的公钥也放在密文里，避免被仓库篡改
+#[derive(Serialize, Deserialize, Debug)]
+struct SealedRequest {
+    body: String,
+    #[serde(default)]
+    payload: Option<serde_json::Value>,
+    reply_key: String,
+}
+
+fn decode_key(value: &str) -> Result<[u8; 32], CryptoError> {
+    let bytes = base64::decode(value.trim()).map_err(|err| CryptoError(err.to_string()))?;
+
+    bytes
+        .try_into()
+        .map_err(|_| CryptoError(String::from("key must be 32 bytes")))
+}
+
+/// 由双方的 X25519 共享密钥派生出对称密钥
+fn derive_cipher(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> ChaCha20Poly1305 {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(&ephemeral.0);
+    salt[32..].copy_from_slice(&recipient.0);
+
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(HKDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    ChaCha20Poly1305::new(&key.into())
+}
+
+/// Encrypts `plaintext` for `recipient`.
+///
+/// A one-time key pair is generated for every call; its public half is
+/// returned in the `CipherHead`. `aad` (the request id) is authenticated but not
+/// encrypted, so a ciphertext cannot be moved to another request.
+///
+/// # Returns
+///
+/// The head to store in `TransHead::cipher`, and the nonce followed by the ciphertext.
+pub fn seal(recipient: &PublicKey, aad: &str, plaintext: &[u8]) -> (CipherHead, Vec<u8>) {
+    let ephemeral = KeyPair::generate();
+    let ephemeral_key = ephemeral.public_key();
+
+    let shared = ephemeral
+        .secret
+        .diffie_hellman(&x25519_dalek::PublicKey::from(recipient.0));
+    let cipher = derive_cipher(shared.as_bytes(), &ephemeral_key, recipient);
+
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .expect("ChaCha20-Poly1305 encryption does not fail for in-memory data");
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+
+    (
+        CipherHead {
+            alg: String::from(ALGORITHM),
+            ephemeral_key: ephemeral_key.to_base64(),
+        },
+        sealed,
+    )
+}
+
+/// Decrypts data produced by `seal` for the public key of `keys`.
+///
+/// # Errors
+///
+/// Fails when the algorithm is unknown, or when the data was not encrypted to
+/// this key pair, was encrypted for another `aad`, or has been tampered with.
+pub fn open(
+    keys: &KeyPair,
+    head: &CipherHead,
+    aad: &str,
+    sealed: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    if head.alg != ALGORITHM {
+        return Err(CryptoError(format!("unsupported cipher {}", head.alg)));
+    }
+
+    if sealed.len() < NONCE_LEN {
+        return Err(CryptoError(String::from("ciphertext is too short")));
+    }
+
+    let ephemeral_key = PublicKey::from_base64(&head.ephemeral_key)?;
+
+    let shared = keys
+        .secret
+        .diffie_hellman(&x25519_dalek::PublicKey::from(ephemeral_key.0));
+    let cipher = derive_cipher(shared.as_bytes(), &ephemeral_key, &keys.public_key());
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| CryptoError(String::from("decryption failed")))
+}
+
+/// Encrypts a text `body` for `recipient` and records the cipher in `head`.
+///
+/// # Returns
+///
+/// The encrypted body, base64-encoded so it can be stored in the mailbox.
+pub fn seal_text(head: &mut TransHead, recipient: &PublicKey, body: &str) -> String {
+    let (cipher, sealed) = seal(recipient, &head.id, body.as_bytes());
+    head.cipher = Some(cipher);
+
+    base64::encode(sealed)
+}
+
+/// Decrypts a body encrypted with `seal_text`. Plaintext bodies (no
+/// `head.cipher`) are returned unchanged.
+pub fn open_text(keys: &KeyPair, head: &TransHead, body: &str) -> Result<String, CryptoError> {
+    let cipher = match &head.cipher {
+        Some(cipher) => cipher,
+        None => return Ok(body.to_string()),
+    };
+
+    let sealed = base64::decode(body).map_err(|err| CryptoError(err.to_string()))?;
+    let plaintext = open(keys, cipher, &head.id, &sealed)?;
+
+    String::from_utf8(plaintext).map_err(|err| CryptoError(err.to_string()))
+}
+
+/// Encrypts the route and payload of `request` for the listener key `agent`.
+///
+/// `reply_key` travels inside the ciphertext and is the key the listener
+/// encrypts the response to.
+pub fn seal_request(request: &mut ReqContent, agent: &PublicKey, reply_key: &PublicKey) {
+    let sealed = SealedRequest {
+        body: request.body.clone(),
+        payload: request.payload.take(),
+        reply_key: reply_key.to_base64(),
+    };
+
+    let plaintext = serde_json::to_string(&sealed).expect("a JSON value always serializes");
+
+    request.body = seal_text(&mut request.head, agent, &plaintext);
+}
+
+/// Decrypts a request encrypted with `seal_request`, restoring its route and payload.
+///
+/// # Returns
+///
+/// The key the response has to be encrypted to, `None` when the request was
+/// not encrypted.
+pub fn open_request(
+    keys: &KeyPair,
+    request: &mut ReqContent,
+) -> Result<Option<PublicKey>, CryptoError> {
+    if request.head.cipher.is_none() {
+        return Ok(None);
+    }
+
+    let plaintext = open_text(keys, &request.head, &request.body)?;
+    let sealed: SealedRequest =
+        serde_json::from_str(&plaintext).map_err(|err| CryptoError(err.to_string()))?;
+
+    request.head.cipher = None;
+    request.body = sealed.body;
+    request.payload = sealed.payload;
+
+    Ok(Some(PublicKey::from_base64(&sealed.reply_key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trans_content::State;
+    use serde_json::json;
+
+    fn request() -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: "req_001".to_string(),
+                state: State::Pending,
+                ..Default::default()
+            },
+            body: "/monitor/pic/shot?resolution=1440x720".to_string(),
+            payload: Some(json!({ "quality": 80 })),
+        }
+    }
+
+    #[test]
+    fn seal_and_open_round_trip() {
+        let keys = KeyPair::generate();
+        let (head, sealed) = seal(&keys.public_key(), "req_001", b"secret");
+
+        assert_eq!(head.alg, ALGORITHM);
+        assert_ne!(&sealed[NONCE_LEN..], b"secret");
+        assert_eq!(open(&keys, &head, "req_001", &sealed).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn every_seal_uses_a_new_key_and_nonce() {
+        let keys = KeyPair::generate();
+        let (first_head, first) = seal(&keys.public_key(), "req_001", b"secret");
+        let (second_head, second) = seal(&keys.public_key(), "req_001", b"secret");
+
+        assert_ne!(first_head.ephemeral_key, second_head.ephemeral_key);
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn tampered_ciphertext_is_rejected() {
+        let keys = KeyPair::generate();
+        let (head, mut sealed) = seal(&keys.public_key(), "req_001", b"secret");
+
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        assert!(open(&keys, &head, "req_001", &sealed).is_err());
+
+        assert!(open(&keys, &head, "req_001", &sealed[..NONCE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn ciphertext_cannot_be_moved_to_another_request() {
+        let keys = KeyPair::generate();
+        let (head, sealed) = seal(&keys.public_key(), "req_001", b"secret");
+
+        let err = open(&keys, &head, "req_002", &sealed).unwrap_err();
+        assert_eq!(err.0, "decryption failed");
+    }
+
+    #[test]
+    fn wrong_recipient_cannot_open() {
+        let keys = KeyPair::generate();
+        let other = KeyPair::generate();
+        let (head, sealed) = seal(&keys.public_key(), "req_001", b"secret");
+
+        assert!(open(&other, &head, "req_001", &sealed).is_err());
+
+        // 换掉一次性公钥同样无法解密
+        let forged = CipherHead {
+            ephemeral_key: other.public_key().to_base64(),
+            ..head.clone()
+        };
+        assert!(open(&keys, &forged, "req_001", &sealed).is_err());
+    }
+
+    #[test]
+    fn unknown_algorithm_is_rejected() {
+        let keys = KeyPair::generate();
+        let (head, sealed) = seal(&keys.public_key(), "req_001", b"secret");
+        let head = CipherHead {
+            alg: "rot13".to_string(),
+            ..head
+        };
+
+        let err = open(&keys, &head, "req_001", &sealed).unwrap_err();
+        assert_eq!(err.0, "unsupported cipher rot13");
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let keys = KeyPair::generate();
+        let mut head = request().head;
+
+        let body = seal_text(&mut head, &keys.public_key(), "pong");
+
+        assert!(head.cipher.is_some());
+        assert_ne!(body, "pong");
+        assert_eq!(open_text(&keys, &head, &body).unwrap(), "pong");
+
+        // 没有 cipher 的正文原样返回
+        let plain = request().head;
+        assert_eq!(open_text(&keys, &plain, "pong").unwrap(), "pong");
+    }
+
+    #[test]
+    fn request_round_trip() {
+        let agent = KeyPair::generate();
+        let reply = KeyPair::generate();
+        let mut sealed = request();
+
+        seal_request(&mut sealed, &agent.public_key(), &reply.public_key());
+
+        assert!(sealed.payload.is_none());
+        assert!(!sealed.body.contains("/monitor"));
+
+        let mut opened = sealed.clone();
+        let reply_key = open_request(&agent, &mut opened).unwrap();
+
+        assert_eq!(reply_key, Some(reply.public_key()));
+        assert!(opened.head.cipher.is_none());
+        assert_eq!(opened.body, request().body);
+        assert_eq!(opened.payload, request().payload);
+
+        // 只有监听端的密钥可以解密请求
+        assert!(open_request(&reply, &mut sealed.clone()).is_err());
+    }
+
+    #[test]
+    fn plaintext_request_is_left_alone() {
+        let agent = KeyPair::generate();
+        let mut plain = request();
+
+        assert_eq!(open_request(&agent, &mut plain).unwrap(), None);
+        assert_eq!(plain.body, request().body);
+    }
+
+    #[test]
+    fn keys_round_trip_through_base64() {
+        let keys = KeyPair::generate();
+        let restored = KeyPair::from_base64(&keys.secret_base64()).unwrap();
+
+        assert_eq!(restored.public_key(), keys.public_key());
+        assert_eq!(
+            PublicKey::from_base64(&keys.public_key().to_base64()).unwrap(),
+            keys.public_key()
+        );
+        assert!(PublicKey::from_base64("c2hvcnQ=").is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use std::{error::Error, fmt, io, time::Duration};
+
+use crate::backend::RevisionConflict;
+use crate::config::ConfigError;
+
+/// Error returned by the transport layer when the mailbox cannot be read or
+/// written.
+///
+/// HTTP statuses returned by Gitee are mapped onto the variants with
+/// `from_status`, so callers can tell a failure worth retrying from one that
+/// needs to be fixed first, see `is_transient`.
+///
+/// # Variants
+///
+/// * `Network` - The server could not be reached, or answered with a status
+///   no other variant covers (`status` is `None` when there was no answer)
+/// * `Auth` - The access token is invalid or lacks permissions (401, 403)
+/// * `NotFound` - The repository, branch or file does not exist (404)
+/// * `Conflict` - The file was modified since it was read (409, or 400/422
+///   complaining about the sha)
+/// * `AlreadyExists` - The file to create exists already (400/422 saying so)
+/// * `RateLimited` - Too many API calls (429, or 403 mentioning the rate limit)
+/// * `PayloadTooLarge` - The file is larger than the server accepts (413)
+/// * `Decode` - The response or the mailbox content cannot be decoded
+/// * `Config` - The configuration cannot be loaded
+/// * `InvalidInput` - A name or path was refused before anything was stored,
+///   e.g. a blob name read from the mailbox that the listener did not generate
+/// * `Other` - An error of a custom backend that no other variant covers
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::error::BtpError;
+///
+/// let err = BtpError::from_status(429, "Too Many Requests");
+/// assert!(err.is_transient());
+///
+/// let err = BtpError::from_status(401, "401 Unauthorized: Access token does not exist");
+/// assert!(matches!(err, BtpError::Auth(_)));
+/// assert!(!err.is_transient());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BtpError {
+    Network {
+        status: Option<u16>,
+        message: String,
+    },
+    Auth(String),
+    NotFound(String),
+    Conflict(String),
+    AlreadyExists(String),
+    RateLimited {
+        retry_after: Option<Duration>,
+        message: String,
+    },
+    PayloadTooLarge(String),
+    Decode(String),
+    Config(ConfigError),
+    InvalidInput(String),
+    Other(String),
+}
+
+impl BtpError {
+    /// Maps an unsuccessful HTTP status and the response body onto an error.
+    ///
+    /// # Parameters
+    ///
+    /// * `status` - HTTP status code of the response
+    /// * `message` - Response body, used as the error message
+    pub fn from_status(status: u16, message: impl Into<String>) -> Self {
+        let message = message.into();
+        let lower = message.to_lowercase();
+
+        match status {
+            401 => BtpError::Auth(message),
+            // gitee 超出调用频率时返回 403
+            403 if lower.contains("rate limit") => BtpError::RateLimited {
+                retry_after: None,
+                message,
+            },
+            403 => BtpError::Auth(message),
+            404 => BtpError::NotFound(message),
+            409 => BtpError::Conflict(message),
+            // 部分版本对过期的 sha 返回 400/422
+            400 | 422 if lower.contains("sha") => BtpError::Conflict(message),
+            // 新建的文件已存在，由调用方决定是否算作冲突；"does not exist" 不算
+            400 | 422 if lower.contains("already exist") => BtpError::AlreadyExists(message),
+            413 => BtpError::PayloadTooLarge(message),
+            429 => BtpError::RateLimited {
+                retry_after: None,
+                message,
+            },
+            status => BtpError::Network {
+                status: Some(status),
+                message,
+            },
+        }
+    }
+
+    /// Returns whether the same call may succeed when it is retried later.
+    ///
+    /// Connection failures, timeouts, server errors (5xx) and rate limiting are
+    /// transient. A `Conflict` is not: the file has to be read again first,
+    /// which `backend::update_mailbox` takes care of.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            BtpError::Network { status: None, .. } => true,
+            BtpError::Network {
+                status: Some(status),
+                ..
+            } => *status == 408 || *status >= 500,
+            BtpError::RateLimited { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns how long the server asked to wait before calling again, if it
+    /// rate-limited the call and said so.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            BtpError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BtpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BtpError::Network {
+                status: Some(status),
+                message,
+            } => write!(f, "request failed with HTTP {}: {}", status, message),
+            BtpError::Network {
+                status: None,
+                message,
+            } => {
+                write!(f, "network error: {}", message)
+            }
+            BtpError::Auth(message) => write!(f, "not authorized: {}", message),
+            BtpError::NotFound(message) => write!(f, "not found: {}", message),
+            BtpError::Conflict(message) => write!(f, "mailbox revision conflict: {}", message),
+            BtpError::AlreadyExists(message) => write!(f, "file already exists: {}", message),
+            BtpError::RateLimited {
+                retry_after: Some(retry_after),
+                message,
+            } => write!(
+                f,
+                "rate limited, retry after {:?}: {}",
+                retry_after, message
+            ),
+            BtpError::RateLimited {
+                retry_after: None,
+                message,
+            } => write!(f, "rate limited: {}", message),
+            BtpError::PayloadTooLarge(message) => write!(f, "payload too large: {}", message),
+            BtpError::Decode(message) => write!(f, "cannot decode response: {}", message),
+            BtpError::Config(err) => write!(f, "{}", err),
+            BtpError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            BtpError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for BtpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BtpError::Config(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for BtpError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_decode() {
+            return BtpError::Decode(err.to_string());
+        }
+
+        match err.status() {
+            Some(status) => BtpError::from_status(status.as_u16(), err.to_string()),
+            None => BtpError::Network {
+                status: None,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+impl From<serde_json::Error> for BtpError {
+    fn from(err: serde_json::Error) -> Self {
+        BtpError::Decode(err.to_string())
+    }
+}
+
+impl From<base64::DecodeError> for BtpError {
+    fn from(err: base64::DecodeError) -> Self {
+        BtpError::Decode(err.to_string())
+    }
+}
+
+impl From<ConfigError> for BtpError {
+    fn from(err: ConfigError) -> Self {
+        BtpError::Config(err)
+    }
+}
+
+impl From<RevisionConflict> for BtpError {
+    fn from(err: RevisionConflict) -> Self {
+        BtpError::Conflict(err.to_string())
+    }
+}
+
+impl From<io::Error> for BtpError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => BtpError::NotFound(err.to_string()),
+            io::ErrorKind::PermissionDenied => BtpError::Auth(err.to_string()),
+            _ => BtpError::Network {
+                status: None,
+                message: err.to_string(),
+            },
+        }
+    }
+}
+
+/// Recovers the error returned by a `MailboxBackend`; errors of other types
+/// are reported as `Other` errors, which are not retried.
+impl From<Box<dyn Error + Send + Sync>> for BtpError {
+    fn from(err: Box<dyn Error + Send + Sync>) -> Self {
+        let err = match err.downcast::<BtpError>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<RevisionConflict>() {
+            Ok(err) => return BtpError::from(*err),
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<ConfigError>() {
+            Ok(err) => return BtpError::Config(*err),
+            Err(err) => err,
+        };
+
+        let err = match err.downcast::<serde_json::Error>() {
+            Ok(err) => return BtpError::from(*err),
+            Err(err) => err,
+        };
+
+        // 不认识的错误不一定能通过重试解决，不当作网络错误
+        match err.downcast::<io::Error>() {
+            Ok(err) => BtpError::from(*err),
+            Err(err) => BtpError::Other(err.to_string()),
+        }
+    }
+}
+
+/// 本地后端用 `RevisionConflict`，gitee 后端用 `BtpError::Conflict` 表示冲突
+pub(crate) fn is_conflict(err: &(dyn Error + Send + Sync + 'static)) -> bool {
+    err.is::<RevisionConflict>()
+        || matches!(err.downcast_ref::<BtpError>(), Some(BtpError::Conflict(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_are_classified() {
+        // 状态码、响应内容，以及期望的错误类型和是否可以重试
+        let cases: Vec<(u16, &str, &str, bool)> = vec![
+            (
+                401,
+                "401 Unauthorized: Access token does not exist",
+                "Auth",
+                false,
+            ),
+            (403, "403 Forbidden", "Auth", false),
+            (403, "API rate limit exceeded", "RateLimited", true),
+            (404, "404 Not Found", "NotFound", false),
+            (409, "409 Conflict", "Conflict", false),
+            (400, "sha is missing or does not match", "Conflict", false),
+            (422, "SHA mismatch", "Conflict", false),
+            (
+                400,
+                "A file with this name already exists",
+                "AlreadyExists",
+                false,
+            ),
+            (422, "File already exists", "AlreadyExists", false),
+            (422, "Branch does not exist", "Network", false),
+            (400, "Reference does not exist", "Network", false),
+            (400, "Bad Request", "Network", false),
+            (408, "Request Timeout", "Network", true),
+            (413, "Request Entity Too Large", "PayloadTooLarge", false),
+            (429, "Too Many Requests", "RateLimited", true),
+            (500, "Internal Server Error", "Network", true),
+            (502, "Bad Gateway", "Network", true),
+            (503, "Service Unavailable", "Network", true),
+        ];
+
+        for (status, message, kind, transient) in cases {
+            let err = BtpError::from_status(status, message);
+
+            let actual = match &err {
+                BtpError::Network { status: code, .. } => {
+                    assert_eq!(*code, Some(status));
+                    "Network"
+                }
+                BtpError::Auth(_) => "Auth",
+                BtpError::NotFound(_) => "NotFound",
+                BtpError::Conflict(_) => "Conflict",
+                BtpError::AlreadyExists(_) => "AlreadyExists",
+                BtpError::RateLimited { .. } => "RateLimited",
+                BtpError::PayloadTooLarge(_) => "PayloadTooLarge",
+                BtpError::Decode(_) => "Decode",
+                BtpError::Config(_) => "Config",
+                BtpError::InvalidInput(_) => "InvalidInput",
+                BtpError::Other(_) => "Other",
+            };
+
+            assert_eq!(
+                (actual, err.is_transient()),
+                (kind, transient),
+                "{} {}",
+                status,
+                message
+            );
+            assert!(err.to_string().contains(message));
+        }
+    }
+
+    #[test]
+    fn backend_errors_are_recovered() {
+        let boxed = |err: Box<dyn Error + Send + Sync>| BtpError::from(err);
+
+        assert_eq!(
+            boxed(Box::new(BtpError::NotFound("io".to_string()))),
+            BtpError::NotFound("io".to_string())
+        );
+        assert!(matches!(
+            boxed(Box::new(RevisionConflict)),
+            BtpError::Conflict(_)
+        ));
+        assert!(matches!(
+            boxed(Box::new(ConfigError::Missing { field: "repo" })),
+            BtpError::Config(ConfigError::Missing { field: "repo" })
+        ));
+        assert!(matches!(
+            boxed(Box::new(io::Error::from(io::ErrorKind::NotFound))),
+            BtpError::NotFound(_)
+        ));
+        assert_eq!(
+            boxed(Box::from("something else")),
+            BtpError::Other("something else".to_string())
+        );
+        assert!(!boxed(Box::from("something else")).is_transient());
+    }
+
+    #[test]
+    fn only_revision_conflicts_count_as_conflicts() {
+        assert!(is_conflict(&RevisionConflict));
+        assert!(is_conflict(&BtpError::Conflict("409".to_string())));
+        assert!(!is_conflict(&BtpError::AlreadyExists("exists".to_string())));
+        assert!(!is_conflict(&BtpError::NotFound("io".to_string())));
+    }
+}
@@ -0,0 +1,136 @@
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
+use base64;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GiteeSha {
+    sha: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GiteeBranch {
+    commit: GiteeSha,
+}
+
+/// 通过 Git Data API 把多个文件和 io 文件放在同一个提交中写入 gitee
+///
+/// 依次创建 blob、tree、commit，最后更新分支指向新的 commit；
+/// 分支或 io 文件在读取之后被修改过时返回 `BtpError::Conflict`，
+/// 此时仓库中不会留下任何一个文件
+pub async fn commit_files(
+    api: &GiteeApi,
+    files: &HashMap<String, Vec<u8>>,
+    content: String,
+    sha: &str,
+) -> Result<(), BtpError> {
+    let repo_url = api.config.repo_url();
+    let file_path: &str = &api.config.file_path;
+    let branch: &str = &api.config.branch;
+
+    // 当前分支指向的 commit
+    let head = utils::check(http::get(api, &format!("{}/branches/{}", repo_url, branch)).await?)
+    .await?
+    .json::<GiteeBranch>()
+    .await?
+    .commit
+    .sha;
+
+    // io 文件在这个 commit 中已经不是读取时的版本，说明被别人修改过
+    let current = utils::check(
+        http::get(
+            api,
+            &format!("{}/contents/{}?ref={}", repo_url, file_path, head),
+        )
+        .await?,
+    )
+    .await?
+    .json::<GiteeSha>()
+    .await?
+    .sha;
+
+    if current != sha {
+        return Err(BtpError::Conflict(format!("{} changed since it was read", file_path)));
+    }
+
+    let mut tree = vec![];
+
+    for (path, file_content) in files
+        .iter()
+        .map(|(name, data)| (&name[..], &data[..]))
+        .chain(std::iter::once((file_path, content.as_bytes())))
+    {
+        let blob = post(
+            api,
+            &format!("{}/git/blobs", repo_url),
+            json!({
+                "content": base64::encode(file_content),
+                "encoding": "base64",
+            }),
+        )
+        .await?;
+
+        tree.push(json!({
+            "path": path,
+            "mode": "100644",
+            "type": "blob",
+            "sha": blob,
+        }));
+    }
+
+    let tree = post(
+        api,
+        &format!("{}/git/trees", repo_url),
+        json!({
+            "base_tree": head,
+            "tree": tree,
+        }),
+    )
+    .await?;
+
+    let commit = post(
+        api,
+        &format!("{}/git/commits", repo_url),
+        json!({
+            "message": "response",
+            "tree": tree,
+            "parents": [head],
+        }),
+    )
+    .await?;
+
+    // 不强制更新，分支在此期间有了新的提交时更新会失败
+    let resp = http::patch_json(
+        api,
+        &format!("{}/git/refs/heads/{}", repo_url, branch),
+        &json!({
+            "sha": commit,
+            "force": false,
+        }),
+    )
+    .await?;
+
+    match utils::check(resp).await {
+        Ok(_) => Ok(()),
+        // 分支无法快进更新，说明有了新的提交
+        Err(BtpError::Network {
+            status: Some(422),
+            message,
+        }) => Err(BtpError::Conflict(message)),
+        Err(err) => Err(err),
+    }
+}
+
+/// 创建 git 对象，返回新对象的 sha
+async fn post(
+    api: &GiteeApi,
+    url: &str,
+    data: serde_json::Value,
+) -> Result<String, BtpError> {
+    let resp = utils::check(http::post_json(api, url, &data).await?).await?;
+
+    Ok(resp.json::<GiteeSha>().await?.sha)
+}
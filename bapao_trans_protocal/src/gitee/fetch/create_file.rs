@@ -1,38 +1,25 @@
-use super::{http, utils};
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
 use base64;
 use std::collections::HashMap;
-use std::error::Error;
 
-/// 将数据更新至 gitee 上的 io 文件
-pub async fn create_file(file_name: &String, file_content: &Vec<u8>) -> Result<(), Box<dyn Error>> {
-    let config: HashMap<String, String> = utils::read_config()?;
-
-    let url = String::from("https://gitee.com/api/v5/repos/")
-        + config.get("user_name").unwrap()
-        + "/"
-        + config.get("repo").unwrap()
-        + "/contents/"
-        + file_name;
+/// 在 gitee 仓库中新建一个文件，用于传输文件类型的响应
+pub async fn create_file(
+    api: &GiteeApi,
+    file_name: &str,
+    file_content: &[u8],
+) -> Result<(), BtpError> {
+    let url = api.config.repo_url() + "/contents/" + file_name;
 
     let mut data = HashMap::new();
     let content_str = base64::encode(file_content);
 
-    data.insert("access_token", "4d1a774f17472e4caa236205cb6155ae");
     data.insert("message", "send file");
     data.insert("content", &content_str);
+    data.insert("branch", &api.config.branch);
 
-    let resp = http::post(&url, &data).await?;
-
-    if resp.status() != 200 {
-        let err_msg: String = resp
-            .text()
-            .await
-            .unwrap_or_else(|err| String::from(err.to_string()));
-
-        let err = Box::<dyn Error>::from(err_msg);
-
-        return Err(err);
-    }
+    utils::check(http::post(api, &url, &data).await?).await?;
 
     Ok(())
 }
@@ -0,0 +1,49 @@
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GiteeFileSha {
+    sha: String,
+}
+
+/// 删除 gitee 仓库中的文件（删除需要带上文件当前的 sha）
+pub async fn delete_file(
+    api: &GiteeApi,
+    file_name: &str,
+) -> Result<(), BtpError> {
+    let url = format!(
+        "{}/contents/{}?ref={}",
+        api.config.repo_url(),
+        file_name,
+        api.config.branch
+    );
+
+    let file = utils::check(http::get(api, &url).await?)
+        .await?
+        .json::<GiteeFileSha>()
+        .await?;
+
+    delete_file_with_sha(api, file_name, &file.sha).await
+}
+
+/// 删除 gitee 仓库中 `sha` 版本的文件，文件已被别人修改过时返回 `BtpError::Conflict`
+pub async fn delete_file_with_sha(
+    api: &GiteeApi,
+    file_path: &str,
+    sha: &str,
+) -> Result<(), BtpError> {
+    let url = api.config.repo_url() + "/contents/" + file_path;
+
+    let mut data = HashMap::new();
+
+    data.insert("sha", sha);
+    data.insert("message", "delete file");
+    data.insert("branch", &api.config.branch);
+
+    utils::check(http::delete(api, &url, &data).await?).await?;
+
+    Ok(())
+}
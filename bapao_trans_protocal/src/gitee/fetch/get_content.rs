@@ -1,11 +1,10 @@
 use base64;
 use serde::{Deserialize, Serialize};
-use serde_json;
 
-use std::collections::HashMap;
-
-use crate::trans_content::ReqContent;
+use crate::error::BtpError;
+use crate::trans_content::{self, ReqContent};
 
+use super::http::{self, GiteeApi};
 use super::utils;
 
 // Gitee返回完整版：
@@ -40,7 +39,7 @@ struct GiteeResponse {
 /// 
 /// # Returns
 /// 
-/// `Result<(Vec<ReqContent>, String), Box<dyn std::error::Error>>`
+/// `Result<(Vec<ReqContent>, String), BtpError>`
 /// 
 /// On success:
 /// * `Vec<ReqContent>` - Parsed request content from the repository
@@ -48,20 +47,32 @@ struct GiteeResponse {
 /// 
 /// # Errors
 /// 
-/// * Network connectivity issues
-/// * Authentication failures (invalid access token)
-/// * Repository or file not found
-/// * Base64 decoding errors
-/// * JSON parsing errors
+/// * `BtpError::Network` - Network connectivity issues or server errors
+/// * `BtpError::Auth` - Authentication failures (invalid access token)
+/// * `BtpError::RateLimited` - Too many API calls
+/// * `BtpError::NotFound` - Repository or file not found
+/// * `BtpError::Decode` - Base64 decoding or JSON parsing errors
+/// 
+/// # Parameters
+/// 
+/// * `api` - Repository, mailbox file and access token to use; the rate-limit
+///   headers of the response are recorded in it
 /// 
 /// # Examples
 /// 
-/// ```rust
-/// use bapao_trans_protocal::gitee::fetch::get_content;
+/// The `gitee` module is internal to the crate, so the example is not
+/// compiled; outside of it the mailbox is read with
+/// `GiteeBackend::read_mailbox`.
+/// 
+/// ```rust,ignore
+/// use crate::config::BapaoConfig;
+/// use crate::gitee::fetch::{get_content, GiteeApi};
 /// 
 /// #[tokio::main]
 /// async fn main() {
-///     match get_content().await {
+///     let api = GiteeApi::new(BapaoConfig::load("bapao.config.json").unwrap()).unwrap();
+/// 
+///     match get_content(&api).await {
 ///         Ok((requests, sha)) => {
 ///             println!("Found {} requests, SHA: {}", requests.len(), sha);
 ///             for req in requests {
@@ -75,7 +86,7 @@ struct GiteeResponse {
 /// 
 /// # Configuration Required
 /// 
-/// This function reads from `bapao.config.json` in the project root:
+/// The config is usually loaded from `bapao.config.json`, see `BapaoConfig`:
 /// ```json
 /// {
 ///   "access_token": "your_gitee_token",
@@ -84,40 +95,26 @@ struct GiteeResponse {
 ///   "file_path": "communication_file"
 /// }
 /// ```
-pub async fn get_content() -> Result<(Vec<ReqContent>, String), Box<dyn std::error::Error>> {
-    let config: HashMap<String, String> = utils::read_config()?;
-
-    let url = String::from("https://gitee.com/api/v5/repos/")
-        + config.get("user_name").unwrap()
-        + "/"
-        + config.get("repo").unwrap()
-        + "/contents/"
-        + config.get("file_path").unwrap()
-        + "?access_token="
-        + config.get("access_token").unwrap();
+/// 
+/// The token is sent in the `Authorization` header.
+pub async fn get_content(
+    api: &GiteeApi,
+) -> Result<(Vec<ReqContent>, String), BtpError> {
+    let url = format!(
+        "{}/contents/{}?ref={}",
+        api.config.repo_url(),
+        api.config.file_path,
+        api.config.branch
+    );
 
-    let resp = reqwest::get(url).await?.json::<GiteeResponse>().await?;
+    let resp = utils::check(http::get(api, &url).await?)
+        .await?
+        .json::<GiteeResponse>()
+        .await?;
 
     let decoded_content_bytes = base64::decode(resp.content)?;
 
-    let decoded_content = bytes_to_str(decoded_content_bytes);
-
-    let tran_content: Vec<ReqContent> = serde_json::from_str(&decoded_content)?;
+    let tran_content: Vec<ReqContent> = trans_content::decode_mailbox(&decoded_content_bytes)?;
 
     Ok((tran_content, resp.sha))
 }
-
-/// 将字节码转成字符串
-/// TODO 感觉这个不需要
-fn bytes_to_str(bytes: Vec<u8>) -> String {
-    let mut strs = String::new();
-
-    for &a in bytes.iter() {
-        if let Some(byte_char) = char::from_u32(a.into()) {
-            strs.push(byte_char);
-        } else {
-            // FIXME 这里会不会有BUG
-        }
-    }
-    strs
-}
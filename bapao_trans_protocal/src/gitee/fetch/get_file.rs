@@ -0,0 +1,53 @@
+use base64;
+use serde::{Deserialize, Serialize};
+
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GiteeFileResponse {
+    content: String,
+    sha: String,
+}
+
+/// 读取 gitee 仓库中的文件内容（文件类型的响应）
+pub async fn get_file(
+    api: &GiteeApi,
+    file_name: &str,
+) -> Result<Vec<u8>, BtpError> {
+    match get_file_with_sha(api, file_name).await? {
+        Some((content, _)) => Ok(content),
+        None => Err(BtpError::NotFound(format!("file not found: {}", file_name))),
+    }
+}
+
+/// 读取 gitee 仓库中的文件内容及其 sha，文件不存在时返回 `None`
+pub async fn get_file_with_sha(
+    api: &GiteeApi,
+    file_path: &str,
+) -> Result<Option<(Vec<u8>, String)>, BtpError> {
+    let url = format!(
+        "{}/contents/{}?ref={}",
+        api.config.repo_url(),
+        file_path,
+        api.config.branch
+    );
+
+    let resp = http::get(api, &url).await?;
+
+    if resp.status() == 404 {
+        return Ok(None);
+    }
+
+    // 文件不存在时 gitee 也可能返回 200 和一个空数组
+    let resp = match utils::check(resp).await?.json::<serde_json::Value>().await? {
+        serde_json::Value::Array(_) => return Ok(None),
+        value => serde_json::from_value::<GiteeFileResponse>(value)?,
+    };
+
+    // gitee 返回的 base64 内容可能带换行
+    let content: String = resp.content.split_whitespace().collect();
+
+    Ok(Some((base64::decode(content)?, resp.sha)))
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct GiteeDirEntry {
+    name: String,
+    #[serde(rename = "type")]
+    entry_type: String,
+}
+
+/// 列出 gitee 仓库中某个目录下的文件名，目录不存在时返回空列表
+pub async fn list_dir(
+    api: &GiteeApi,
+    dir: &str,
+) -> Result<Vec<String>, BtpError> {
+    let url = format!(
+        "{}/contents/{}?ref={}",
+        api.config.repo_url(),
+        dir,
+        api.config.branch
+    );
+
+    let resp = http::get(api, &url).await?;
+
+    if resp.status() == 404 {
+        return Ok(vec![]);
+    }
+
+    let entries = utils::check(resp).await?.json::<Vec<GiteeDirEntry>>().await?;
+
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.entry_type == "file")
+        .map(|entry| entry.name)
+        .collect())
+}
@@ -1,9 +1,20 @@
+mod commit_files;
 mod create_file;
+mod delete_file;
 mod get_content;
+mod get_file;
 mod http;
+mod list_dir;
 mod put_content;
+mod put_file;
 mod utils;
 
+pub use self::commit_files::*;
 pub use self::create_file::*;
+pub use self::delete_file::*;
 pub use self::get_content::*;
+pub use self::get_file::*;
+pub use self::http::GiteeApi;
+pub use self::list_dir::*;
 pub use self::put_content::*;
+pub use self::put_file::*;
@@ -1,41 +1,29 @@
-use super::{http, utils};
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
 use base64;
 use std::collections::HashMap;
-use std::error::Error;
 
 /// 将数据更新至 gitee 上的 io 文件
-pub async fn put_content(content: String, sha: String) -> Result<(), Box<dyn Error>> {
-    let config: HashMap<String, String> = utils::read_config()?;
-
-    let url = String::from("https://gitee.com/api/v5/repos/")
-        + config.get("user_name").unwrap()
-        + "/"
-        + config.get("repo").unwrap()
-        + "/contents/"
-        + config.get("file_path").unwrap();
-
-    let mut data = HashMap::new();
+///
+/// `sha` 不是文件的最新版本时（文件在读取之后被别人改过），返回 `BtpError::Conflict`
+pub async fn put_content(
+    api: &GiteeApi,
+    content: String,
+    sha: String,
+) -> Result<(), BtpError> {
+    let url = api.config.repo_url() + "/contents/" + &api.config.file_path;
+
+    let mut data: HashMap<&str, &str> = HashMap::new();
     let content_str = base64::encode(content);
 
-    let token: &str = config.get("access_token").unwrap();
-
-    data.insert("access_token", token);
     data.insert("sha", &sha);
     data.insert("message", "response");
     data.insert("content", &content_str);
+    data.insert("branch", &api.config.branch);
 
-    let resp = http::put(&url, &data).await?;
-
-    if resp.status() != 200 {
-        let err_msg: String = resp
-            .text()
-            .await
-            .unwrap_or_else(|err| String::from(err.to_string()));
-
-        let err = Box::<dyn Error>::from(err_msg);
-
-        return Err(err);
-    }
+    utils::check(http::put(api, &url, &data).await?).await?;
 
     Ok(())
 }
+
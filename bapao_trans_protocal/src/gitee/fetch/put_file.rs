@@ -0,0 +1,38 @@
+use super::http::{self, GiteeApi};
+use super::utils;
+use crate::error::BtpError;
+use base64;
+use std::collections::HashMap;
+
+/// 写入 gitee 仓库中的文件
+///
+/// `sha` 为 `None` 时新建文件，否则更新 `sha` 版本的文件；
+/// 新建的文件已存在时返回 `BtpError::AlreadyExists`，
+/// 文件已被别人修改过时返回 `BtpError::Conflict`
+pub async fn put_file(
+    api: &GiteeApi,
+    file_path: &str,
+    file_content: &[u8],
+    sha: Option<&str>,
+) -> Result<(), BtpError> {
+    let url = api.config.repo_url() + "/contents/" + file_path;
+
+    let mut data = HashMap::new();
+    let content_str = base64::encode(file_content);
+
+    data.insert("message", "update file");
+    data.insert("content", &content_str);
+    data.insert("branch", &api.config.branch);
+
+    let resp = match sha {
+        Some(sha) => {
+            data.insert("sha", sha);
+            http::put(api, &url, &data).await?
+        }
+        None => http::post(api, &url, &data).await?,
+    };
+
+    utils::check(resp).await?;
+
+    Ok(())
+}
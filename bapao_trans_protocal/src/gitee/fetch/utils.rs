@@ -1,11 +1,31 @@
-use std::{collections::HashMap, fs, path};
+use reqwest::{header::RETRY_AFTER, Response};
+use std::time::Duration;
 
-pub fn read_config() -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
-    let config_string = fs::read_to_string(
-        path::Path::new(&std::env::current_dir().unwrap()).join("bapao.config.json"),
-    )?;
+use crate::error::BtpError;
 
-    let config: HashMap<String, String> = serde_json::from_str(&config_string)?;
+/// 状态码不是 2xx 时，按状态码和返回内容转换成对应的错误
+pub async fn check(resp: Response) -> Result<Response, BtpError> {
+    let status = resp.status();
 
-    return Result::Ok(config);
+    if status.is_success() {
+        return Ok(resp);
+    }
+
+    // Retry-After 只处理秒数的写法
+    let retry_after = resp
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs);
+
+    let err_msg: String = resp.text().await.unwrap_or_else(|err| err.to_string());
+
+    Err(match BtpError::from_status(status.as_u16(), err_msg) {
+        BtpError::RateLimited { message, .. } => BtpError::RateLimited {
+            retry_after,
+            message,
+        },
+        err => err,
+    })
 }
@@ -1,23 +1,26 @@
-use crate::trans_content::ReqContent;
+use crate::trans_content::{ReqContent, State};
 
 #[derive(Debug)]
 pub struct ContentGroupByState {
     pub pending: Vec<ReqContent>,
+    pub processing: Vec<ReqContent>,
     pub done: Vec<ReqContent>,
 }
 
 /// 将请求数据根据数据的状态（state）做分组
+///
+/// `done` 中是所有已经结束的数据（Done / Error / Expired / Cancelled）
 pub fn group_by_state(content: Vec<ReqContent>) -> ContentGroupByState {
     let mut content_group_by_state = ContentGroupByState {
         pending: vec![],
+        processing: vec![],
         done: vec![],
     };
 
     for item in content.into_iter() {
-        let state = &item.head.state[..];
-
-        match state {
-            "Pending" => content_group_by_state.pending.push(item),
+        match item.head.state {
+            State::Pending => content_group_by_state.pending.push(item),
+            State::Processing => content_group_by_state.processing.push(item),
             _ => content_group_by_state.done.push(item),
         }
     }
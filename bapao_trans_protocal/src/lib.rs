@@ -1,233 +1,576 @@
-mod gitee;
-pub mod trans_content;
-pub mod trans_unit;
-mod utils;
-
-use gitee::{
-    fetch::{self as gitee_fetch},
-    handler::{self as gitee_handler},
-};
-use serde_json;
-use std::collections::HashMap;
-use trans_content::{ReqContent, ResContentType, ResStringContent};
-use trans_unit::TransUnit;
-use uuid::Uuid;
-
-/// Transport protocol listener for Gitee-based communication.
-/// 
-/// `BtpListener` handles the low-level communication with Gitee repositories,
-/// including fetching requests, managing responses, and handling file transfers.
-/// 
-/// # Examples
-/// 
-/// ```rust
-/// use bapao_trans_protocal::BtpListener;
-/// 
-/// #[tokio::main]
-/// async fn main() {
-///     let mut listener = BtpListener::new();
-///     
-///     // Process requests
-///     let requests = listener.accept().await;
-///     for request in requests {
-///         // Handle request and create response
-///         let response = request.set(TransUnitType::String("OK".to_string()));
-///         listener.stash(response);
-///     }
-/// }
-/// ```
-pub struct BtpListener {
-    done: Vec<ResStringContent>,
-    files: HashMap<String, Vec<u8>>,
-}
-
-impl BtpListener {
-    /// Creates a new `BtpListener` instance.
-    /// 
-    /// Initializes empty storage for completed responses and file data.
-    /// 
-    /// # Returns
-    /// 
-    /// A new `BtpListener` ready to handle transport operations.
-    /// 
-    /// # Examples
-    /// 
-    /// ```rust
-    /// use bapao_trans_protocal::BtpListener;
-    /// 
-    /// let mut listener = BtpListener::new();
-    /// ```
-    pub fn new() -> Self {
-        BtpListener {
-            done: vec![],
-            files: HashMap::new(),
-        }
-    }
-
-    /// Fetches new requests from the Gitee repository and returns pending requests.
-    /// 
-    /// This method polls the configured Gitee repository, processes the content,
-    /// and returns any pending requests that need to be handled. It also sends
-    /// any previously stashed responses back to the repository.
-    /// 
-    /// # Returns
-    /// 
-    /// `Vec<TransUnit>` - A vector of pending requests to process
-    /// 
-    /// # Behavior
-    /// 
-    /// - Fetches content from Gitee repository
-    /// - Filters out expired requests (older than 30 minutes)
-    /// - Groups requests by state (Pending/Done)
-    /// - Sends stashed responses to repository
-    /// - Returns only pending requests for processing
-    /// 
-    /// # Examples
-    /// 
-    /// ```rust
-    /// use bapao_trans_protocal::BtpListener;
-    /// 
-    /// #[tokio::main]
-    /// async fn main() {
-    ///     let mut listener = BtpListener::new();
-    ///     
-    ///     loop {
-    ///         let requests = listener.accept().await;
-    ///         
-    ///         for request in requests {
-    ///             println!("Processing: {}", request.get());
-    ///             // Handle request...
-    ///         }
-    ///         
-    ///         tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-    ///     }
-    /// }
-    /// ```
-    pub async fn accept(&mut self) -> Vec<TransUnit> {
-        // 获取gitee数据
-        let (trans_content, sha) = gitee_fetch::get_content().await.unwrap_or_else(|err| {
-            eprintln!("获取gitee内容出错：");
-            eprintln!("{:#?}", err);
-
-            // FIXME
-            (vec![], String::from(""))
-        });
-
-        // 将获取到的数据按照 (已处理\未处理) 进行分类
-        // FIXME 如果是很久之前就发出的Pending 数据呢？是不是应该失效掉
-        let grouped_content = gitee_handler::group_by_state(trans_content);
-
-        if grouped_content.pending.len() == 0 && self.done.len() == 0 {
-            println!("无数据需要传输！");
-            return vec![];
-        } else {
-            println!(
-                "接收到新的请求：{} 个。已处理的待响应请求：{} 个。",
-                grouped_content.pending.len(),
-                self.done.len()
-            );
-        }
-
-        self._send(sha, grouped_content.done).await;
-
-        grouped_content
-            .pending
-            .into_iter()
-            .map(|content| TransUnit::new(content))
-            .collect()
-    }
-
-    /// Temporarily stores a response without immediately sending it to Gitee.
-    /// 
-    /// Responses are queued and will be sent to the repository during the next
-    /// `accept()` call. This allows batching multiple responses together for
-    /// more efficient communication.
-    /// 
-    /// # Parameters
-    /// 
-    /// * `value` - The response content to store
-    /// 
-    /// # Behavior
-    /// 
-    /// - String responses are stored directly in the done queue
-    /// - File responses are assigned a UUID filename and stored separately
-    /// - Files will be uploaded to Gitee as separate files
-    /// - String responses will be included in the main communication file
-    /// 
-    /// # Examples
-    /// 
-    /// ```rust
-    /// use bapao_trans_protocal::{BtpListener, trans_content::*};
-    /// 
-    /// let mut listener = BtpListener::new();
-    /// 
-    /// // Stash a string response
-    /// let response = ResContentType::String(ResStringContent {
-    ///     head: TransHead {
-    ///         id: "req_123".to_string(),
-    ///         content_type: Some("string".to_string()),
-    ///         state: "Done".to_string(),
-    ///         timestamp: 1234567890,
-    ///     },
-    ///     body: "Response data".to_string(),
-    /// });
-    /// 
-    /// listener.stash(response);
-    /// ```
-    pub fn stash(&mut self, value: ResContentType) -> () {
-        match value {
-            ResContentType::String(val) => {
-                self.done.push(val);
-            }
-
-            ResContentType::File(val) => {
-                let file_name = Uuid::new_v4().to_string();
-                let file_content = val.body;
-                self.files.insert(file_name.clone(), file_content);
-                self.done.push(ResStringContent {
-                    head: val.head,
-                    body: file_name,
-                });
-            }
-        }
-    }
-
-    async fn _send(&mut self, sha: String, trans_content_vec: Vec<ReqContent>) -> () {
-        // 发送 文件 内容
-        let file_map = &self.files;
-        // file_map.into_iter().for_each(|(file_name, file_content)| {
-        //     let _ = async {
-        //         let _ = gitee_fetch::create_file(file_name, file_content).await;
-        //     };
-        // });
-
-        for (file_name, file_content) in file_map.into_iter() {
-            let _ = gitee_fetch::create_file(file_name, file_content).await;
-        }
-
-        let _ = &mut &self.files.clear();
-
-        // FIXME 删除gitee中的失效文件
-        // FIXME 不需要等待请求响应成功失败，只要发送出去就行，以提高系统效率
-
-        // 发送io 内容
-        let mut trimed_content = utils::trim_expired_data(trans_content_vec);
-
-        // 将当前已经处理完毕的数据 与 之前存起来的数据合并
-        trimed_content.append(&mut self.done);
-
-        let content = serde_json::to_string(&trimed_content).unwrap_or_else(|err| {
-            println!("生成 io 内容出错！");
-            println!("Cause: {}", err);
-            // 出错就只能空数组兜底了
-            String::from("[]")
-        });
-
-        gitee_fetch::put_content(content, sha)
-            .await
-            .unwrap_or_else(|err| {
-                println!("更新数据出错！");
-                println!("Cause: {}", err);
-            });
-    }
-}
+pub mod auth;
+pub mod backend;
+pub mod chunk;
+pub mod clock;
+pub mod config;
+pub mod crypto;
+pub mod error;
+mod gitee;
+pub mod replay;
+pub mod retry;
+pub mod scheduler;
+pub mod trans_content;
+pub mod trans_unit;
+mod utils;
+
+use auth::TrustList;
+use backend::{GiteeBackend, MailboxBackend};
+use chunk::ChunkManifest;
+use clock::Clock;
+use crypto::{KeyPair, PublicKey};
+use error::BtpError;
+use gitee::handler::{self as gitee_handler};
+use replay::ReplayGuard;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use trans_content::{ErrorCode, ReqContent, ResContentType, ResStringContent, State, TransError, TransUnitType};
+use trans_unit::TransUnit;
+use uuid::Uuid;
+
+/// Transport protocol listener for mailbox-based communication.
+/// 
+/// `BtpListener` handles the low-level communication with the mailbox,
+/// including fetching requests, managing responses, and handling file transfers.
+/// The mailbox is reached through a `MailboxBackend`, which defaults to
+/// `GiteeBackend`.
+/// 
+/// # Examples
+/// 
+/// ```rust,no_run
+/// use bapao_trans_protocal::{trans_content::TransUnitType, BtpListener};
+/// 
+/// #[tokio::main]
+/// async fn main() {
+///     let mut listener = BtpListener::new();
+///     
+///     // Process requests
+///     let requests = listener.accept().await.unwrap_or_default();
+///     for request in requests {
+///         // Handle request and create response
+///         let response = request.set(TransUnitType::String("OK".to_string()));
+///         listener.stash(response);
+///     }
+/// }
+/// ```
+pub struct BtpListener<B = GiteeBackend> {
+    backend: B,
+    done: Vec<ResStringContent>,
+    files: HashMap<String, Vec<u8>>,
+    processing: HashSet<String>,
+    chunk_size: usize,
+    keys: Option<KeyPair>,
+    reply_keys: HashMap<String, PublicKey>,
+    trust_list: Option<TrustList>,
+    replay: ReplayGuard,
+    clock: Arc<dyn Clock>,
+    expiry: chrono::Duration,
+}
+
+impl BtpListener<GiteeBackend> {
+    /// Creates a new `BtpListener` instance backed by Gitee.
+    /// 
+    /// Initializes empty storage for completed responses and file data.
+    /// 
+    /// # Returns
+    /// 
+    /// A new `BtpListener` ready to handle transport operations.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::BtpListener;
+    /// 
+    /// let mut listener = BtpListener::new();
+    /// ```
+    pub fn new() -> Self {
+        BtpListener::with_backend(GiteeBackend::new())
+    }
+}
+
+impl Default for BtpListener<GiteeBackend> {
+    fn default() -> Self {
+        BtpListener::new()
+    }
+}
+
+impl<B> BtpListener<B>
+where
+    B: MailboxBackend,
+{
+    /// Creates a new `BtpListener` that reaches the mailbox through `backend`.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `backend` - The mailbox backend used for every read and write
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{backend::GiteeBackend, BtpListener};
+    /// 
+    /// let mut listener = BtpListener::with_backend(GiteeBackend::new());
+    /// ```
+    pub fn with_backend(backend: B) -> Self {
+        BtpListener {
+            backend,
+            done: vec![],
+            files: HashMap::new(),
+            processing: HashSet::new(),
+            chunk_size: chunk::DEFAULT_CHUNK_SIZE,
+            keys: None,
+            reply_keys: HashMap::new(),
+            trust_list: None,
+            replay: ReplayGuard::in_memory(),
+            clock: clock::system(),
+            expiry: chrono::Duration::minutes(utils::EXPIRY_MINUTES),
+        }
+    }
+
+    /// Returns the mailbox backend used by this listener.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Sets the size above which file responses are split into chunks.
+    /// 
+    /// Defaults to `chunk::DEFAULT_CHUNK_SIZE` (512 KiB). Lower it when the
+    /// mailbox host rejects or truncates files of that size.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `bytes` - Maximum size of a single blob (at least 1)
+    pub fn set_chunk_size(&mut self, bytes: usize) {
+        self.chunk_size = bytes.max(1);
+    }
+
+    /// Enables end-to-end encryption with the listener key pair `keys`.
+    /// 
+    /// Clients encrypt their requests to the public key of `keys`, and every
+    /// response is encrypted to the key the client sent along with its request,
+    /// see `crypto`. Once enabled, plaintext requests are answered with a
+    /// `BadRequest` error instead of being handled. Rejections of requests
+    /// that cannot be verified or decrypted are the only plaintext responses.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{crypto::KeyPair, BtpListener};
+    /// 
+    /// let keys = KeyPair::generate();
+    /// println!("agent_public_key: {}", keys.public_key().to_base64());
+    /// 
+    /// let mut listener = BtpListener::new();
+    /// listener.set_encryption(keys);
+    /// ```
+    pub fn set_encryption(&mut self, keys: KeyPair) {
+        self.keys = Some(keys);
+    }
+
+    /// Only accepts requests signed by one of the clients in `trust_list`.
+    /// 
+    /// Signatures are checked before a request is decrypted or handed out.
+    /// Unsigned requests, requests signed with an unknown key id and requests
+    /// whose signature does not match are answered with an `Unauthorized`
+    /// error. The key id of the verified client is available through
+    /// `TransUnit::client_id` and `RequestContext::client_id`.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{auth::{SigningKey, TrustList}, BtpListener};
+    /// 
+    /// let client_key = SigningKey::generate("laptop");
+    /// 
+    /// let mut trusted = TrustList::new();
+    /// trusted.add("laptop", &client_key.verify_key_base64()).unwrap();
+    /// 
+    /// let mut listener = BtpListener::new();
+    /// listener.set_trust_list(trusted);
+    /// ```
+    pub fn set_trust_list(&mut self, trust_list: TrustList) {
+        self.trust_list = Some(trust_list);
+    }
+
+    /// Sets the record of dispatched requests used to reject replays.
+    /// 
+    /// By default the record is kept in memory only, so a request copied back
+    /// into the mailbox after a restart would run again. Use
+    /// `ReplayGuard::open` to persist it in a file; the record is saved
+    /// before the accepted requests are handed out. The guard takes over the
+    /// clock and expiry window of the listener.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust,no_run
+    /// use bapao_trans_protocal::{replay::ReplayGuard, BtpListener};
+    /// 
+    /// let mut listener = BtpListener::new();
+    /// listener.set_replay_guard(ReplayGuard::open("bapao.replay.json").unwrap());
+    /// ```
+    pub fn set_replay_guard(&mut self, mut guard: ReplayGuard) {
+        guard.set_clock(self.clock.clone());
+        guard.set_window(self.expiry);
+
+        self.replay = guard;
+    }
+
+    /// Sets the clock the listener reads the current time from.
+    /// 
+    /// The clock decides which requests are expired, which ones the replay
+    /// guard rejects as too old, and the timestamp of every response. It
+    /// defaults to the system clock; a `clock::ManualClock` lets tests move
+    /// through the expiry window without waiting.
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{backend::MemoryBackend, clock::ManualClock, BtpListener};
+    /// use chrono::Utc;
+    /// use std::sync::Arc;
+    /// 
+    /// let clock = ManualClock::new(Utc::now());
+    /// 
+    /// let mut listener = BtpListener::with_backend(MemoryBackend::new());
+    /// listener.set_clock(Arc::new(clock.clone()));
+    /// ```
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.replay.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /// Returns the clock the listener reads the current time from.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.clock
+    }
+
+    /// Sets how long requests stay valid.
+    /// 
+    /// Defaults to 30 minutes. Older requests are marked `Expired` instead of
+    /// being handled, finished entries are removed from the mailbox once
+    /// older than `expiry`, and `Expired` ones once older than twice `expiry`.
+    /// The replay guard uses the same window.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `expiry` - Validity of a request, counted from its timestamp
+    pub fn set_expiry(&mut self, expiry: Duration) {
+        self.expiry =
+            chrono::Duration::from_std(expiry).unwrap_or_else(|_| chrono::Duration::max_value());
+        self.replay.set_window(self.expiry);
+    }
+
+    /// Fetches new requests from the mailbox and returns pending requests.
+    /// 
+    /// This method polls the configured mailbox backend, processes the content,
+    /// and returns any pending requests that need to be handled. It also sends
+    /// any previously stashed responses back to the repository.
+    /// 
+    /// # Returns
+    /// 
+    /// `Vec<TransUnit>` - A vector of pending requests to process
+    /// 
+    /// # Errors
+    /// 
+    /// Returns the `BtpError` of the backend when the mailbox cannot be read or
+    /// written. Stashed responses and files are kept and sent by the next call;
+    /// `BtpError::is_transient` tells whether retrying is worthwhile.
+    /// 
+    /// # Behavior
+    /// 
+    /// - Fetches content from the mailbox backend
+    /// - Marks requests older than the expiry window (30 minutes by default, see
+    ///   `set_expiry`) as `Expired` and drops stale entries; this is written
+    ///   back even when there is nothing else to send
+    /// - Groups requests by state (Pending/Processing/finished)
+    /// - Sends stashed responses to repository, replacing their requests; a
+    ///   response is kept for the next call until it has been written
+    /// - Commits the blobs of file responses together with the mailbox update
+    ///   through `MailboxBackend::commit`
+    /// - Writes accepted requests back as `Processing`, and only returns them
+    ///   once that write succeeded
+    /// - Picks up `Processing` requests this listener does not know about,
+    ///   i.e. requests accepted before a crash or restart; those that were
+    ///   already dispatched before are rejected rather than run twice
+    /// - Rejects requests whose id or nonce was handled before, or that are
+    ///   too old, see `set_replay_guard`
+    /// - Re-reads and merges again when the mailbox was modified concurrently,
+    ///   so entries written by clients in the meantime are never overwritten
+    /// - Returns only the accepted requests for processing
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust,no_run
+    /// use bapao_trans_protocal::BtpListener;
+    /// 
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut listener = BtpListener::new();
+    ///     
+    ///     loop {
+    ///         match listener.accept().await {
+    ///             Ok(requests) => {
+    ///                 for request in requests {
+    ///                     println!("Processing: {}", request.get());
+    ///                     // Handle request...
+    ///                 }
+    ///             }
+    ///             Err(err) if err.is_transient() => eprintln!("will retry: {}", err),
+    ///             Err(err) => panic!("cannot reach the mailbox: {}", err),
+    ///         }
+    ///         
+    ///         tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+    ///     }
+    /// }
+    /// ```
+    pub async fn accept(&mut self) -> Result<Vec<TransUnit>, BtpError> {
+        let done = &self.done;
+        let done_ids: HashSet<String> = done.iter().map(|item| item.head.id.clone()).collect();
+        let processing = &self.processing;
+        let clock = &self.clock;
+        let expiry = self.expiry;
+
+        // mailbox 被客户端同时修改时，会重新读取并再次合并，不会覆盖对方的数据
+        // 文件和响应一起提交，文件没有全部上传成功时不会写入引用它们的响应
+        let result = backend::commit_mailbox(&self.backend, clock.as_ref(), &mut self.files, |trans_content| {
+            // 很久之前发出的请求标记为 Expired，不再处理
+            let states: Vec<State> = trans_content.iter().map(|item| item.head.state).collect();
+            let trans_content = utils::trim_expired_data(trans_content, clock.now(), expiry);
+
+            // 只会移除条目或修改状态，状态序列不变说明没有过期的条目
+            let trimmed = trans_content
+                .iter()
+                .map(|item| item.head.state)
+                .ne(states);
+
+            // 已处理完毕的响应替换掉对应的请求
+            let trans_content = trans_content
+                .into_iter()
+                .filter(|item| !done_ids.contains(&item.head.id))
+                .collect();
+
+            // 将获取到的数据按照 (未处理\处理中\已结束) 进行分类
+            let grouped_content = gitee_handler::group_by_state(trans_content);
+
+            // 不是当前进程在处理的 Processing 请求，说明接收之后进程中断了，需要重新处理
+            let (orphaned, ours): (Vec<ReqContent>, Vec<ReqContent>) = grouped_content
+                .processing
+                .into_iter()
+                .partition(|item| !processing.contains(&item.head.id));
+
+            let mut accepted = grouped_content.pending;
+            accepted.extend(orphaned);
+
+            // 没有新请求和响应时，过期的标记和清理也要写回
+            if accepted.is_empty() && done.is_empty() && !trimmed {
+                println!("无数据需要传输！");
+                return Ok((None, vec![]));
+            }
+
+            println!(
+                "接收到新的请求：{} 个。已处理的待响应请求：{} 个。",
+                accepted.len(),
+                done.len()
+            );
+
+            // 接收的请求先以 Processing 状态写回去，避免处理期间中断导致请求丢失
+            let mut content = grouped_content.done;
+            content.extend(ours);
+            content.extend(accepted.iter().cloned().map(|mut item| {
+                item.head.state = State::Processing;
+                item
+            }));
+            content.extend(done.iter().cloned());
+
+            Ok((Some(content), accepted))
+        })
+        .await;
+
+        // 响应和没上传的文件保留下来，下次再发送；没能标记为 Processing 的请求下次再接收
+        let accepted = result.map_err(BtpError::from)?;
+
+        self.done.clear();
+
+        for id in done_ids.iter() {
+            self.processing.remove(id);
+        }
+
+        for item in accepted.iter() {
+            self.processing.insert(item.head.id.clone());
+        }
+
+        let accepted: Vec<TransUnit> = accepted
+            .into_iter()
+            .filter_map(|item| self.open_request(item))
+            .collect();
+
+        // 分发之前先保存记录，进程中断后这些请求也不会再次执行
+        if let Err(err) = self.replay.save() {
+            println!("保存请求记录出错！");
+            println!("Cause: {}", err);
+        }
+
+        Ok(accepted)
+    }
+
+    /// 验证签名并解密请求，无法处理的请求直接以错误响应
+    fn open_request(&mut self, mut item: ReqContent) -> Option<TransUnit> {
+        // 先验证签名，签名覆盖的是密文，未通过验证的请求不解密
+        let client_id = match &self.trust_list {
+            Some(trust_list) => match trust_list.verify(&item) {
+                Ok(client_id) => Some(client_id),
+                Err(err) => {
+                    self.reject(item, TransError::new(ErrorCode::Unauthorized, err.to_string()));
+                    return None;
+                }
+            },
+            None => None,
+        };
+
+        let result = match (&self.keys, item.head.cipher.is_some()) {
+            (Some(keys), true) => crypto::open_request(keys, &mut item)
+                .map_err(|err| format!("cannot decrypt request: {}", err)),
+            (Some(_), false) => Err(String::from("request must be encrypted")),
+            (None, true) => Err(String::from("encryption is not enabled on the listener")),
+            (None, false) => Ok(None),
+        };
+
+        match result {
+            Ok(reply_key) => {
+                // 解密之后才知道回复的公钥，之后的拒绝也能加密给客户端
+                if let Some(reply_key) = reply_key {
+                    self.reply_keys.insert(item.head.id.clone(), reply_key);
+                }
+
+                // 重复的请求不再执行
+                if let Err(err) = self.replay.check(&item) {
+                    self.reject(item, err);
+                    return None;
+                }
+
+                let unit = TransUnit::new(item).with_clock(self.clock.clone());
+
+                Some(match client_id {
+                    Some(client_id) => unit.with_client_id(client_id),
+                    None => unit,
+                })
+            }
+            Err(message) => {
+                self.reject(item, TransError::new(ErrorCode::BadRequest, message));
+                None
+            }
+        }
+    }
+
+    fn reject(&mut self, item: ReqContent, err: TransError) {
+        println!("拒绝请求 {}：{}", item.head.id, err.message);
+
+        let unit = TransUnit::new(item).with_clock(self.clock.clone());
+
+        self.stash(unit.set(TransUnitType::Error(err)));
+    }
+
+    /// Temporarily stores a response without immediately sending it to Gitee.
+    /// 
+    /// Responses are queued and will be sent to the repository during the next
+    /// `accept()` call. This allows batching multiple responses together for
+    /// more efficient communication.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `value` - The response content to store
+    /// 
+    /// # Behavior
+    /// 
+    /// - String responses are stored directly in the done queue
+    /// - File responses are assigned a UUID filename and stored separately
+    /// - File responses larger than the chunk size (see `set_chunk_size`) are
+    ///   split into chunks named `<uuid>.<n>`, and the response body becomes a
+    ///   `ChunkManifest` with content type `chunked_file`
+    /// - Chunks that fail to upload stay queued and are retried on the next
+    ///   `accept()` call; with the default `MailboxBackend::commit`, chunks
+    ///   already stored are not sent again
+    /// - Files are committed together with the mailbox update, see
+    ///   `MailboxBackend::commit`
+    /// - String responses will be included in the main communication file
+    /// - Responses to encrypted requests are encrypted to the client's key,
+    ///   file content before it is split into chunks
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{BtpListener, trans_content::*};
+    /// 
+    /// let mut listener = BtpListener::new();
+    /// 
+    /// // Stash a string response
+    /// let response = ResContentType::String(ResStringContent {
+    ///     head: TransHead {
+    ///         id: "req_123".to_string(),
+    ///         content_type: Some("string".to_string()),
+    ///         state: State::Done,
+    ///         timestamp: 1234567890,
+    ///         ..Default::default()
+    ///     },
+    ///     body: "Response data".to_string(),
+    ///     payload: None,
+    /// });
+    /// 
+    /// listener.stash(response);
+    /// ```
+    pub fn stash(&mut self, value: ResContentType) {
+        // 请求是加密的，响应也加密给发出请求的客户端
+        let reply_key = match &value {
+            ResContentType::String(val) => self.reply_keys.remove(&val.head.id),
+            ResContentType::File(val) => self.reply_keys.remove(&val.head.id),
+        };
+
+        let value = match (value, reply_key) {
+            (ResContentType::String(mut val), Some(reply_key)) => {
+                val.body = crypto::seal_text(&mut val.head, &reply_key, &val.body);
+                ResContentType::String(val)
+            }
+            (ResContentType::File(mut val), Some(reply_key)) => {
+                let (cipher, sealed) = crypto::seal(&reply_key, &val.head.id, &val.body);
+                val.head.cipher = Some(cipher);
+                val.body = sealed;
+                ResContentType::File(val)
+            }
+            (value, None) => value,
+        };
+
+        match value {
+            ResContentType::String(val) => {
+                self.done.push(val);
+            }
+
+            ResContentType::File(val) if val.body.len() > self.chunk_size => {
+                // 大文件拆分成多个文件上传，响应内容为清单
+                let file_name = Uuid::new_v4().to_string();
+                let (manifest, chunks) = ChunkManifest::split(&file_name, &val.body, self.chunk_size);
+
+                self.files.extend(chunks);
+
+                let mut head = val.head;
+                head.content_type = Some(String::from(chunk::CHUNKED_FILE));
+
+                self.done.push(ResStringContent {
+                    head,
+                    body: serde_json::to_string(&manifest).unwrap_or_default(),
+                    payload: None,
+                });
+            }
+
+            ResContentType::File(val) => {
+                let file_name = Uuid::new_v4().to_string();
+                let file_content = val.body;
+                self.files.insert(file_name.clone(), file_content);
+                self.done.push(ResStringContent {
+                    head: val.head,
+                    body: file_name,
+                    payload: None,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::backend;
+use crate::clock::{self, Clock};
+use crate::trans_content::{ErrorCode, ReqContent, TransError};
+use crate::utils::EXPIRY_MINUTES;
+
+/// How far the timestamp of a request may lie in the future, to allow for
+/// clocks that are slightly off.
+pub const MAX_CLOCK_SKEW_MINUTES: i64 = 5;
+
+/// 持久化的内容：请求 id 和 nonce，以及对应请求的时间戳
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ReplayRecord {
+    ids: HashMap<String, i64>,
+    nonces: HashMap<String, i64>,
+}
+
+/// Record of the requests a listener has dispatched, used to reject replays.
+///
+/// The mailbox is a plain file, so an entry that was handled before can be
+/// copied back into it. `check` remembers the id and the `nonce` of every
+/// request it lets through, and rejects a request when
+///
+/// - its id or nonce was seen before (`Replayed`),
+/// - it is older than the expiry window (`Expired`), 30 minutes by default, or
+/// - its timestamp is more than `MAX_CLOCK_SKEW_MINUTES` in the future (`BadRequest`).
+///
+/// A record is kept until its request is older than the expiry window; from
+/// then on the age check rejects the request anyway. Since the signature
+/// covers the timestamp and nonce (see `auth`), a replayed entry cannot be
+/// refreshed without the client's key.
+///
+/// The current time is read from a `Clock`; the listener hands its own clock
+/// and expiry window to the guard, see `BtpListener::set_clock`.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::replay::ReplayGuard;
+/// use bapao_trans_protocal::trans_content::{ReqContent, TransHead};
+///
+/// let mut guard = ReplayGuard::in_memory();
+///
+/// let request = ReqContent {
+///     head: TransHead {
+///         id: "req_001".to_string(),
+///         timestamp: chrono::Utc::now().timestamp_millis(),
+///         nonce: Some("6f1c".to_string()),
+///         ..Default::default()
+///     },
+///     body: "/ping".to_string(),
+///     payload: None,
+/// };
+///
+/// assert!(guard.check(&request).is_ok());
+/// assert!(guard.check(&request).is_err());
+/// ```
+#[derive(Debug)]
+pub struct ReplayGuard {
+    path: Option<PathBuf>,
+    record: ReplayRecord,
+    clock: Arc<dyn Clock>,
+    window: Duration,
+}
+
+impl Default for ReplayGuard {
+    fn default() -> Self {
+        ReplayGuard {
+            path: None,
+            record: ReplayRecord::default(),
+            clock: clock::system(),
+            window: Duration::minutes(EXPIRY_MINUTES),
+        }
+    }
+}
+
+impl ReplayGuard {
+    /// Creates a guard that only remembers requests while the process runs.
+    pub fn in_memory() -> Self {
+        ReplayGuard::default()
+    }
+
+    /// Creates a guard persisted in the JSON file at `path`.
+    ///
+    /// The file is loaded if it exists and is rewritten by `save`.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the file exists but cannot be read or parsed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let path = path.as_ref().to_path_buf();
+
+        let record = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => ReplayRecord::default(),
+            Err(err) => return Err(Box::new(err)),
+        };
+
+        Ok(ReplayGuard {
+            path: Some(path),
+            record,
+            ..Default::default()
+        })
+    }
+
+    /// Reads the current time from `clock` instead of the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// 监听端的时钟和过期时间同步给 guard
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(crate) fn set_window(&mut self, window: Duration) {
+        self.window = window;
+    }
+
+    /// Checks that `request` is neither a replay nor too old, and records it.
+    ///
+    /// # Errors
+    ///
+    /// The error to answer the request with; a rejected request is not recorded.
+    pub fn check(&mut self, request: &ReqContent) -> Result<(), TransError> {
+        let now = self.clock.now().timestamp_millis();
+        let window = self.window.num_milliseconds();
+        let skew = Duration::minutes(MAX_CLOCK_SKEW_MINUTES).num_milliseconds();
+
+        self.prune(now - window);
+
+        let head = &request.head;
+
+        if head.timestamp <= now - window {
+            return Err(TransError::new(
+                ErrorCode::Expired,
+                "request is older than the expiry window",
+            ));
+        }
+
+        if head.timestamp > now + skew {
+            return Err(TransError::new(
+                ErrorCode::BadRequest,
+                "request timestamp is in the future",
+            ));
+        }
+
+        if self.record.ids.contains_key(&head.id) {
+            return Err(TransError::new(
+                ErrorCode::Replayed,
+                "request id was already handled",
+            ));
+        }
+
+        if let Some(nonce) = &head.nonce {
+            if self.record.nonces.contains_key(nonce) {
+                return Err(TransError::new(
+                    ErrorCode::Replayed,
+                    "request nonce was already used",
+                ));
+            }
+
+            self.record.nonces.insert(nonce.clone(), head.timestamp);
+        }
+
+        self.record.ids.insert(head.id.clone(), head.timestamp);
+
+        Ok(())
+    }
+
+    /// Writes the record to its file, if the guard has one.
+    pub fn save(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        backend::write_atomic(path, &serde_json::to_vec(&self.record)?)?;
+
+        Ok(())
+    }
+
+    /// 超出有效期的请求由时间检查拒绝，不必再记录
+    fn prune(&mut self, limit: i64) {
+        self.record.ids.retain(|_, timestamp| *timestamp > limit);
+        self.record.nonces.retain(|_, timestamp| *timestamp > limit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::trans_content::TransHead;
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    fn clock() -> ManualClock {
+        ManualClock::new(Utc.ymd(2024, 3, 1).and_hms(12, 0, 0))
+    }
+
+    fn request(id: &str, nonce: &str, clock: &ManualClock) -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: id.to_string(),
+                timestamp: clock.now().timestamp_millis(),
+                nonce: Some(nonce.to_string()),
+                ..Default::default()
+            },
+            body: "/ping".to_string(),
+            payload: None,
+        }
+    }
+
+    /// 每个测试一个单独的临时文件
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("bapao-replay-{}", Uuid::new_v4()))
+            .join("replay.json")
+    }
+
+    fn code(result: Result<(), TransError>) -> Option<ErrorCode> {
+        result.err().map(|err| err.code)
+    }
+
+    #[test]
+    fn reused_nonce_is_rejected() {
+        let clock = clock();
+        let mut guard = ReplayGuard::in_memory().with_clock(Arc::new(clock.clone()));
+
+        assert_eq!(code(guard.check(&request("req_001", "n1", &clock))), None);
+        assert_eq!(
+            code(guard.check(&request("req_002", "n1", &clock))),
+            Some(ErrorCode::Replayed)
+        );
+        assert_eq!(
+            code(guard.check(&request("req_001", "n2", &clock))),
+            Some(ErrorCode::Replayed)
+        );
+    }
+
+    #[test]
+    fn timestamps_outside_the_window_are_rejected() {
+        let clock = clock();
+        let mut guard = ReplayGuard::in_memory().with_clock(Arc::new(clock.clone()));
+
+        let mut future = request("req_001", "n1", &clock);
+        future.head.timestamp += Duration::minutes(MAX_CLOCK_SKEW_MINUTES + 1).num_milliseconds();
+        assert_eq!(code(guard.check(&future)), Some(ErrorCode::BadRequest));
+
+        let mut old = request("req_002", "n2", &clock);
+        old.head.timestamp -= Duration::minutes(EXPIRY_MINUTES).num_milliseconds();
+        assert_eq!(code(guard.check(&old)), Some(ErrorCode::Expired));
+
+        // 被拒绝的请求不会被记录
+        assert!(guard.record.ids.is_empty());
+        assert!(guard.record.nonces.is_empty());
+    }
+
+    #[test]
+    fn old_records_are_pruned() {
+        let clock = clock();
+        let mut guard = ReplayGuard::in_memory().with_clock(Arc::new(clock.clone()));
+        guard.set_window(Duration::minutes(10));
+
+        guard.check(&request("req_001", "n1", &clock)).unwrap();
+
+        clock.advance(std::time::Duration::from_secs(5 * 60));
+        guard.check(&request("req_002", "n2", &clock)).unwrap();
+        assert_eq!(guard.record.ids.len(), 2);
+
+        // req_001 超出有效期之后不再记录，req_002 还在有效期内
+        clock.advance(std::time::Duration::from_secs(6 * 60));
+        guard.check(&request("req_003", "n3", &clock)).unwrap();
+
+        let mut ids: Vec<&String> = guard.record.ids.keys().collect();
+        ids.sort();
+        assert_eq!(ids, vec!["req_002", "req_003"]);
+        assert!(!guard.record.nonces.contains_key("n1"));
+    }
+
+    #[test]
+    fn record_survives_a_restart() {
+        let clock = clock();
+        let path = temp_path();
+
+        // 文件不存在时从空记录开始
+        let mut guard = ReplayGuard::open(&path)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        guard.check(&request("req_001", "n1", &clock)).unwrap();
+        guard.save().unwrap();
+
+        let mut restarted = ReplayGuard::open(&path)
+            .unwrap()
+            .with_clock(Arc::new(clock.clone()));
+        assert_eq!(
+            code(restarted.check(&request("req_001", "n1", &clock))),
+            Some(ErrorCode::Replayed)
+        );
+        assert_eq!(
+            code(restarted.check(&request("req_002", "n2", &clock))),
+            None
+        );
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_is_an_error() {
+        let path = temp_path();
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not json").unwrap();
+
+        assert!(ReplayGuard::open(&path).is_err());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn in_memory_guard_does_not_write() {
+        assert!(ReplayGuard::in_memory().save().is_ok());
+    }
+}
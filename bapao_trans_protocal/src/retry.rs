@@ -0,0 +1,231 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rand_core::{OsRng, RngCore};
+use reqwest::header::HeaderMap;
+use std::{future::Future, time::Duration};
+
+use crate::clock::{Clock, SystemClock};
+use crate::error::BtpError;
+
+/// Share of the API budget below which polling is slowed down.
+pub const LOW_BUDGET_RATIO: f64 = 0.2;
+
+/// 响应头中没有重置时间时，假定额度在一小时内恢复
+const DEFAULT_BUDGET_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// How often, and how long apart, a failed API call is retried.
+///
+/// Only transient errors (see `BtpError::is_transient`) are retried. The delay
+/// before retry `n` is `initial_delay * factor^(n - 1)`, capped at `max_delay`,
+/// of which a random part between 50% and 100% is waited ("equal jitter"), so
+/// several processes sharing a mailbox do not retry in lockstep. When the
+/// server asks to retry after a given time (`BtpError::RateLimited`), that time
+/// is waited instead, unless it exceeds `max_delay`: then the call gives up and
+/// the rate limit is left to the poll loop.
+///
+/// # Fields
+///
+/// * `max_attempts` - Number of calls made at most, including the first one
+/// * `initial_delay` - Delay before the first retry
+/// * `max_delay` - Upper bound of a single delay
+/// * `factor` - Factor the delay grows by after every retry
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::retry::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy {
+///     max_attempts: 5,
+///     initial_delay: Duration::from_millis(200),
+///     ..Default::default()
+/// };
+///
+/// let delay = policy.delay(2);
+/// assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub factor: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            factor: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes every call exactly once.
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the jittered delay before retry `retry` (starting at 1).
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+
+        let delay = self
+            .initial_delay
+            .mul_f64(self.factor.max(1.0).powi(exponent).min(u32::MAX as f64))
+            .min(self.max_delay);
+
+        // 在 50% 到 100% 之间随机取值
+        let jitter = OsRng.next_u32() as f64 / u32::MAX as f64;
+
+        delay.mul_f64(0.5 + jitter / 2.0)
+    }
+
+    /// Calls `call` until it succeeds, fails with an error that is not
+    /// transient, or `max_attempts` calls were made.
+    ///
+    /// # Errors
+    ///
+    /// The error of the last call.
+    pub async fn run<T, F, Fut>(&self, call: F) -> Result<T, BtpError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BtpError>>,
+    {
+        self.run_with_clock(&SystemClock, call).await
+    }
+
+    /// Same as `run`, waiting between the calls with `clock`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use bapao_trans_protocal::{clock::ManualClock, error::BtpError, retry::RetryPolicy};
+    /// use chrono::Utc;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let clock = ManualClock::new(Utc::now());
+    ///
+    ///     let result: Result<(), BtpError> = RetryPolicy::default()
+    ///         .run_with_clock(&clock, || async { Err(BtpError::Network { status: None, message: "reset".to_string() }) })
+    ///         .await;
+    ///
+    ///     // 4 calls, 3 waits, no time actually spent
+    ///     assert!(result.is_err());
+    ///     assert_eq!(clock.slept().len(), 3);
+    /// }
+    /// ```
+    pub async fn run_with_clock<T, F, Fut>(
+        &self,
+        clock: &dyn Clock,
+        mut call: F,
+    ) -> Result<T, BtpError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, BtpError>>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let err = match call().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+
+            if !err.is_transient() || attempt >= self.max_attempts {
+                return Err(err);
+            }
+
+            let delay = match err.retry_after() {
+                Some(retry_after) if retry_after > self.max_delay => return Err(err),
+                Some(retry_after) => retry_after,
+                None => self.delay(attempt),
+            };
+
+            println!(
+                "调用 gitee API 出错，{:?} 后重试（第 {} 次）：{}",
+                delay, attempt, err
+            );
+
+            clock.sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// API calls left according to the rate-limit headers of the last response.
+///
+/// Read from `X-RateLimit-Limit`, `X-RateLimit-Remaining` and
+/// `X-RateLimit-Reset` (seconds since the epoch); responses without
+/// `X-RateLimit-Remaining` leave the budget unchanged.
+///
+/// # Fields
+///
+/// * `limit` - Number of calls allowed per window, if the server reports it
+/// * `remaining` - Number of calls left in the current window
+/// * `reset` - When the window resets, in seconds since the epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateBudget {
+    pub limit: Option<u64>,
+    pub remaining: u64,
+    pub reset: Option<i64>,
+}
+
+impl RateBudget {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<i64>().ok())
+        };
+
+        Some(RateBudget {
+            limit: number("x-ratelimit-limit").map(|limit| limit.max(0) as u64),
+            remaining: number("x-ratelimit-remaining")?.max(0) as u64,
+            reset: number("x-ratelimit-reset"),
+        })
+    }
+
+    /// Returns whether less than `LOW_BUDGET_RATIO` of the budget is left.
+    ///
+    /// Without a reported `limit`, fewer than 10 remaining calls count as low.
+    pub fn is_low(&self) -> bool {
+        match self.limit {
+            Some(limit) => (self.remaining as f64) < limit as f64 * LOW_BUDGET_RATIO,
+            None => self.remaining < 10,
+        }
+    }
+
+    /// Returns how long to wait between polls so that the remaining calls last
+    /// until the window resets.
+    ///
+    /// Zero while the budget is not low. A poll takes at least one call, so the
+    /// time until the reset is spread over the remaining calls; once none are
+    /// left, the whole time until the reset is waited.
+    pub fn pacing(&self) -> Duration {
+        self.pacing_at(Utc::now())
+    }
+
+    /// Same as `pacing`, counting the time until the reset from `now`.
+    pub fn pacing_at(&self, now: DateTime<Utc>) -> Duration {
+        if !self.is_low() {
+            return Duration::ZERO;
+        }
+
+        let until_reset = self
+            .reset
+            .and_then(|reset| Utc.timestamp_opt(reset, 0).single())
+            .map(|reset| (reset - now).to_std().unwrap_or_default())
+            .unwrap_or(DEFAULT_BUDGET_WINDOW);
+
+        until_reset / self.remaining.clamp(1, u32::MAX as u64) as u32
+    }
+}
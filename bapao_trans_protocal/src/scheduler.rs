@@ -0,0 +1,324 @@
+use chrono::{DateTime, Local, NaiveTime};
+use std::{sync::Arc, time::Duration};
+
+use crate::clock::{self, Clock};
+
+/// Daily time window in local wall-clock time, `start` included and `end`
+/// excluded.
+///
+/// A window whose `end` is before its `start` wraps around midnight, e.g.
+/// `22:00`–`07:00`. It is used as `QuietHours` by the poll scheduler and as
+/// `AllowedHours` by the policies of `bapao_app_protocal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HourWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+/// Daily time window during which the listener stops polling.
+pub type QuietHours = HourWindow;
+
+impl HourWindow {
+    /// Creates a window from `start` until `end`.
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        HourWindow { start, end }
+    }
+
+    /// Returns whether `time` falls inside the window.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+
+    /// Returns how long it takes from `time` until the window ends.
+    fn remaining(&self, time: NaiveTime) -> Duration {
+        let remaining = self.end.signed_duration_since(time);
+
+        let remaining = if remaining < chrono::Duration::zero() {
+            remaining + chrono::Duration::days(1)
+        } else {
+            remaining
+        };
+
+        remaining.to_std().unwrap_or_default()
+    }
+}
+
+/// Settings of the adaptive poll scheduler.
+///
+/// # Fields
+///
+/// * `min_interval` - Delay between polls while requests keep arriving
+/// * `max_interval` - Upper bound of the delay once the mailbox stays idle
+/// * `backoff_factor` - Factor the delay grows by after every idle poll
+/// * `quiet_hours` - Optional daily window during which polling is paused
+///
+/// `PollScheduler::new` swaps `min_interval` and `max_interval` when they are
+/// given the wrong way round. A `backoff_factor` below 1 (or NaN) is treated
+/// as 1, and one too large to compute the next delay jumps straight to
+/// `max_interval`.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::scheduler::{PollSchedule, QuietHours};
+/// use chrono::NaiveTime;
+/// use std::time::Duration;
+///
+/// let schedule = PollSchedule {
+///     min_interval: Duration::from_secs(5),
+///     max_interval: Duration::from_secs(120),
+///     quiet_hours: Some(QuietHours::new(
+///         NaiveTime::from_hms(22, 0, 0),
+///         NaiveTime::from_hms(7, 0, 0),
+///     )),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PollSchedule {
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    pub backoff_factor: f64,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+impl Default for PollSchedule {
+    fn default() -> Self {
+        PollSchedule {
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(60),
+            backoff_factor: 2.0,
+            quiet_hours: None,
+        }
+    }
+}
+
+/// Decides how long to wait before the next poll of the mailbox.
+///
+/// The delay drops back to `min_interval` as soon as a poll finds work, and
+/// grows by `backoff_factor` up to `max_interval` after every idle poll. During
+/// quiet hours the delay lasts until the quiet window ends.
+///
+/// The scheduler only computes delays; the caller does the (non-blocking)
+/// waiting, e.g. with `Clock::sleep`. Quiet hours are checked against the
+/// time of a `Clock`, the system clock unless set with `with_clock`.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_trans_protocal::scheduler::{PollSchedule, PollScheduler};
+/// use std::time::Duration;
+///
+/// let mut scheduler = PollScheduler::new(PollSchedule::default());
+///
+/// assert_eq!(scheduler.next_delay(true), Duration::from_secs(10));
+/// assert_eq!(scheduler.next_delay(false), Duration::from_secs(20));
+/// assert_eq!(scheduler.next_delay(false), Duration::from_secs(40));
+/// assert_eq!(scheduler.next_delay(false), Duration::from_secs(60));
+/// assert_eq!(scheduler.next_delay(true), Duration::from_secs(10));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PollScheduler {
+    schedule: PollSchedule,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl PollScheduler {
+    /// Creates a scheduler starting at `schedule.min_interval`.
+    pub fn new(mut schedule: PollSchedule) -> Self {
+        // 上下限颠倒时交换，保证 min_interval <= max_interval
+        if schedule.min_interval > schedule.max_interval {
+            std::mem::swap(&mut schedule.min_interval, &mut schedule.max_interval);
+        }
+
+        let interval = schedule.min_interval;
+
+        PollScheduler {
+            schedule,
+            interval,
+            clock: clock::system(),
+        }
+    }
+
+    /// Reads the time of day for quiet hours from `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the schedule this scheduler follows.
+    pub fn schedule(&self) -> &PollSchedule {
+        &self.schedule
+    }
+
+    /// Records the outcome of a poll and returns the delay before the next one.
+    ///
+    /// # Parameters
+    ///
+    /// * `active` - Whether the poll found requests to handle or responses to send
+    pub fn next_delay(&mut self, active: bool) -> Duration {
+        self.next_delay_at(active, self.clock.local_now())
+    }
+
+    fn next_delay_at(&mut self, active: bool, now: DateTime<Local>) -> Duration {
+        self.interval = if active {
+            self.schedule.min_interval
+        } else {
+            // 系数过大或为无穷大时，下一次间隔超出 Duration 的范围，直接用最大间隔
+            Duration::try_from_secs_f64(
+                self.interval.as_secs_f64() * self.schedule.backoff_factor.max(1.0),
+            )
+            .unwrap_or(self.schedule.max_interval)
+            .clamp(self.schedule.min_interval, self.schedule.max_interval)
+        };
+
+        match self.schedule.quiet_hours {
+            Some(quiet_hours) if quiet_hours.contains(now.time()) => {
+                // 静默时段内一直等到时段结束，结束后从最小间隔重新开始
+                self.interval = self.schedule.min_interval;
+
+                quiet_hours.remaining(now.time())
+            }
+            _ => self.interval,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use chrono::{TimeZone, Utc};
+
+    fn at(hour: u32, minute: u32) -> ManualClock {
+        let time = Local.ymd(2024, 3, 1).and_hms(hour, minute, 0);
+
+        ManualClock::new(time.with_timezone(&Utc))
+    }
+
+    fn scheduler(schedule: PollSchedule, clock: &ManualClock) -> PollScheduler {
+        PollScheduler::new(schedule).with_clock(Arc::new(clock.clone()))
+    }
+
+    fn secs(delays: Vec<Duration>) -> Vec<u64> {
+        delays.iter().map(Duration::as_secs).collect()
+    }
+
+    fn quiet(start: u32, end: u32) -> QuietHours {
+        QuietHours::new(
+            NaiveTime::from_hms(start, 0, 0),
+            NaiveTime::from_hms(end, 0, 0),
+        )
+    }
+
+    #[test]
+    fn idle_polls_back_off_and_activity_resets() {
+        let clock = at(12, 0);
+        let mut scheduler = scheduler(PollSchedule::default(), &clock);
+
+        let delays = (0..5).map(|_| scheduler.next_delay(false)).collect();
+        assert_eq!(secs(delays), vec![20, 40, 60, 60, 60]);
+
+        assert_eq!(scheduler.next_delay(true), Duration::from_secs(10));
+        assert_eq!(scheduler.next_delay(false), Duration::from_secs(20));
+    }
+
+    #[test]
+    fn factor_below_one_keeps_the_minimum() {
+        let clock = at(12, 0);
+
+        for backoff_factor in [0.5, -3.0, f64::NAN] {
+            let mut scheduler = scheduler(
+                PollSchedule {
+                    backoff_factor,
+                    ..Default::default()
+                },
+                &clock,
+            );
+
+            assert_eq!(scheduler.next_delay(false), Duration::from_secs(10));
+        }
+    }
+
+    #[test]
+    fn huge_factor_jumps_to_the_maximum() {
+        let clock = at(12, 0);
+
+        for backoff_factor in [1e300, f64::INFINITY] {
+            let mut scheduler = scheduler(
+                PollSchedule {
+                    backoff_factor,
+                    max_interval: Duration::MAX,
+                    ..Default::default()
+                },
+                &clock,
+            );
+
+            assert_eq!(scheduler.next_delay(false), Duration::MAX);
+            assert_eq!(scheduler.next_delay(false), Duration::MAX);
+        }
+    }
+
+    #[test]
+    fn reversed_bounds_are_swapped() {
+        let clock = at(12, 0);
+        let mut scheduler = scheduler(
+            PollSchedule {
+                min_interval: Duration::from_secs(60),
+                max_interval: Duration::from_secs(10),
+                ..Default::default()
+            },
+            &clock,
+        );
+
+        assert_eq!(scheduler.schedule().min_interval, Duration::from_secs(10));
+        assert_eq!(scheduler.schedule().max_interval, Duration::from_secs(60));
+        assert_eq!(scheduler.next_delay(false), Duration::from_secs(20));
+        assert_eq!(scheduler.next_delay(true), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn quiet_hours_wrap_around_midnight() {
+        let hours = quiet(22, 7);
+
+        assert!(hours.contains(NaiveTime::from_hms(22, 0, 0)));
+        assert!(hours.contains(NaiveTime::from_hms(3, 0, 0)));
+        assert!(!hours.contains(NaiveTime::from_hms(7, 0, 0)));
+        assert!(!hours.contains(NaiveTime::from_hms(12, 0, 0)));
+
+        let hours = quiet(12, 14);
+
+        assert!(hours.contains(NaiveTime::from_hms(13, 0, 0)));
+        assert!(!hours.contains(NaiveTime::from_hms(23, 0, 0)));
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_pause_until_the_window_ends() {
+        let clock = at(21, 50);
+        let mut scheduler = scheduler(
+            PollSchedule {
+                quiet_hours: Some(quiet(22, 7)),
+                ..Default::default()
+            },
+            &clock,
+        );
+
+        assert_eq!(scheduler.next_delay(false), Duration::from_secs(20));
+
+        // 进入静默时段后一直等到早上 7 点
+        clock.advance(Duration::from_secs(15 * 60));
+        let delay = scheduler.next_delay(false);
+        assert_eq!(delay, Duration::from_secs((8 * 60 + 55) * 60));
+
+        clock.sleep(delay).await;
+        assert_eq!(clock.local_now().time(), NaiveTime::from_hms(7, 0, 0));
+
+        // 静默时段结束后从最小间隔重新开始退避
+        assert_eq!(scheduler.next_delay(false), Duration::from_secs(20));
+    }
+}
@@ -1,4 +1,39 @@
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+
+/// Lifecycle state of a mailbox entry.
+/// 
+/// A client writes a request as `Pending`. The listener marks it `Processing`
+/// as soon as it accepts it, so a crash before the response is written does not
+/// lose the request, and finally replaces it with a `Done` or `Error` response.
+/// Requests nobody handled within the expiry window become `Expired`, and a
+/// client may withdraw a pending request by marking it `Cancelled`.
+/// 
+/// Serialized as the variant name (`"Pending"`, `"Done"`, ...). Any other value
+/// is rejected when the mailbox is parsed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum State {
+    #[default]
+    Pending,
+    Processing,
+    Done,
+    Error,
+    Expired,
+    Cancelled,
+}
+
+impl State {
+    /// Returns whether the entry is finished, i.e. nothing will change it anymore.
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, State::Pending | State::Processing)
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
 
 /// Metadata header for all transport communications.
 /// 
@@ -8,31 +43,71 @@ use serde::{Deserialize, Serialize};
 /// # Fields
 /// 
 /// * `id` - Unique identifier for the request/response pair
-/// * `content_type` - Type of content: "string", "file", or None for requests
-/// * `state` - Processing state: "Pending" for requests, "Done" for responses
+/// * `content_type` - Type of content: "string", "file", "error", or None for requests
+/// * `state` - Processing state, see `State`
 /// * `timestamp` - Unix timestamp in milliseconds when the request was created
+/// * `headers` - Free-form metadata sent along with a request, omitted when empty
+/// * `cipher` - How the content is encrypted, omitted for plaintext
+/// * `nonce` - Random value identifying a request, see `replay`
+/// * `key_id` - Id of the client key the request is signed with, see `auth`
+/// * `signature` - Base64 Ed25519 signature of the request, see `auth`
 /// 
 /// # Examples
 /// 
 /// ```rust
 /// use bapao_trans_protocal::trans_content::TransHead;
 /// 
+/// use bapao_trans_protocal::trans_content::State;
+/// 
 /// let header = TransHead {
 ///     id: "req_001".to_string(),
 ///     content_type: Some("string".to_string()),
-///     state: "Done".to_string(),
+///     state: State::Done,
 ///     timestamp: chrono::Utc::now().timestamp_millis(),
+///     ..Default::default()
 /// };
 /// ```
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct TransHead {
     pub id: String,
-    /// Data type being transmitted: "file" for binary data, "string" for text data
+    /// Data type being transmitted: "file" for binary data, "string" for text data,
+    /// "error" for a JSON-encoded `TransError`
     pub content_type: Option<String>,
-    /// Processing state: "Pending" for new requests, "Done" for completed responses
-    pub state: String,
+    /// Processing state, validated when the mailbox is parsed
+    pub state: State,
     /// Unix timestamp in milliseconds when the request was created
     pub timestamp: i64,
+    /// Request metadata such as a client name or a trace id
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Encryption of the content, see `crypto`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<CipherHead>,
+    /// Random value generated by the client for every request, see `replay`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Id of the client key that signed the request, see `auth`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Signature of the request, see `auth`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+/// Describes how the content of an entry is encrypted.
+/// 
+/// For requests, string and error responses the `body` is encrypted (base64
+/// of the nonce and ciphertext). For file responses the blob is encrypted and
+/// the `body` (blob name or chunk manifest) stays readable.
+/// 
+/// # Fields
+/// 
+/// * `alg` - Encryption scheme, `crypto::ALGORITHM`
+/// * `ephemeral_key` - Base64 X25519 public key generated for this entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CipherHead {
+    pub alg: String,
+    pub ephemeral_key: String,
 }
 
 /// Request content structure for incoming communications.
@@ -43,7 +118,9 @@ pub struct TransHead {
 /// # Fields
 /// 
 /// * `head` - Request metadata (ID, state, timestamp, etc.)
-/// * `body` - Request content, typically a route path or command
+/// * `body` - Request route, optionally followed by query parameters
+///   (e.g. `/monitor/pic/shot?resolution=1440x720`)
+/// * `payload` - Optional JSON data for the handler, omitted when absent
 /// 
 /// # Examples
 /// 
@@ -54,16 +131,20 @@ pub struct TransHead {
 ///     head: TransHead {
 ///         id: "req_001".to_string(),
 ///         content_type: None,
-///         state: "Pending".to_string(),
+///         state: State::Pending,
 ///         timestamp: chrono::Utc::now().timestamp_millis(),
+///         ..Default::default()
 ///     },
-///     body: "/api/status".to_string(),
+///     body: "/monitor/pic/shot?format=png".to_string(),
+///     payload: Some(serde_json::json!({ "display": 1 })),
 /// };
 /// ```
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ReqContent {
     pub head: TransHead,
     pub body: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>,
 }
 
 /// Response content structure for binary file responses.
@@ -101,14 +182,23 @@ pub type ResStringContent = ReqContent;
 /// 
 /// // Text response
 /// let text_response = ResContentType::String(ResStringContent {
-///     head: TransHead { /* ... */ },
+///     head: TransHead {
+///         id: "req_123".to_string(),
+///         state: State::Done,
+///         ..Default::default()
+///     },
 ///     body: "Hello, World!".to_string(),
+///     payload: None,
 /// });
 /// 
 /// // File response
-/// let file_data = std::fs::read("image.png").unwrap();
+/// let file_data = vec![0x89, 0x50, 0x4e, 0x47];
 /// let file_response = ResContentType::File(ResFileContent {
-///     head: TransHead { /* ... */ },
+///     head: TransHead {
+///         id: "req_124".to_string(),
+///         state: State::Done,
+///         ..Default::default()
+///     },
 ///     body: file_data,
 /// });
 /// ```
@@ -126,11 +216,12 @@ pub enum ResContentType {
 /// 
 /// * `String(String)` - Text data
 /// * `File(Vec<u8>)` - Binary file data
+/// * `Error(TransError)` - The request failed; sent back with the `Error` state
 /// 
 /// # Examples
 /// 
 /// ```rust
-/// use bapao_trans_protocal::trans_content::TransUnitType;
+/// use bapao_trans_protocal::trans_content::{ErrorCode, TransError, TransUnitType};
 /// 
 /// // Return text data
 /// fn text_handler() -> TransUnitType {
@@ -139,11 +230,153 @@ pub enum ResContentType {
 /// 
 /// // Return file data
 /// fn file_handler() -> TransUnitType {
-///     let file_data = std::fs::read("document.pdf").unwrap();
-///     TransUnitType::File(file_data)
+///     match std::fs::read("document.pdf") {
+///         Ok(file_data) => TransUnitType::File(file_data),
+///         // Tell the client why the request failed
+///         Err(err) => TransUnitType::Error(TransError::new(ErrorCode::Internal, err.to_string())),
+///     }
 /// }
 /// ```
+#[derive(Debug, Clone, PartialEq)]
 pub enum TransUnitType {
     String(String),
     File(Vec<u8>),
+    Error(TransError),
+}
+
+impl From<TransError> for TransUnitType {
+    fn from(err: TransError) -> Self {
+        TransUnitType::Error(err)
+    }
+}
+
+/// Reason a request failed, sent back to the client in an `Error` response.
+/// 
+/// # Variants
+/// 
+/// * `BadRequest` - The request is malformed, e.g. invalid parameters or payload
+/// * `NotFound` - No handler is registered for the requested route
+/// * `Internal` - The handler failed or panicked
+/// * `Expired` - Nobody handled the request within the expiry window
+/// * `Cancelled` - The client cancelled the request before it was handled
+/// * `Unauthorized` - The request is unsigned, or its signature does not match
+///   a trusted client key
+/// * `Replayed` - A request with the same id or nonce was already handled
+/// * `Forbidden` - The client may not call the route, or not at this time
+/// * `RateLimited` - The client called the route too often
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    NotFound,
+    Internal,
+    Expired,
+    Cancelled,
+    Unauthorized,
+    Replayed,
+    Forbidden,
+    RateLimited,
+}
+
+/// Error response body: a machine-readable code and a human-readable message.
+/// 
+/// Stored as JSON in the body of a response whose state is `Error` and whose
+/// content type is `error`:
+/// 
+/// ```json
+/// { "code": "NotFound", "message": "no handler registered for route /foo" }
+/// ```
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// use bapao_trans_protocal::trans_content::{ErrorCode, TransError};
+/// 
+/// let err = TransError::new(ErrorCode::BadRequest, "resolution must look like 1440x720");
+/// assert_eq!(err.code, ErrorCode::BadRequest);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TransError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl TransError {
+    /// Creates an error with the given code and message.
+    pub fn new<M: Into<String>>(code: ErrorCode, message: M) -> Self {
+        TransError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for TransError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for TransError {}
+
+/// Parses the content of a mailbox file.
+/// 
+/// Entries are validated one by one, so a single malformed entry does not make
+/// the whole mailbox unreadable. An invalid entry that still carries an id is
+/// replaced by an `Error` entry telling its client why it was rejected; one
+/// without a usable id is dropped.
+/// 
+/// # Errors
+/// 
+/// Fails only when `content` is not a JSON array.
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// use bapao_trans_protocal::trans_content::{decode_mailbox, State};
+/// 
+/// let entries = decode_mailbox(br#"[
+///     {"head": {"id": "a", "content_type": null, "state": "Pending", "timestamp": 0}, "body": "/ping"},
+///     {"head": {"id": "b", "content_type": null, "state": "Waiting", "timestamp": 0}, "body": "/ping"}
+/// ]"#).unwrap();
+/// 
+/// assert_eq!(entries[0].head.state, State::Pending);
+/// assert_eq!(entries[1].head.state, State::Error);
+/// ```
+pub fn decode_mailbox(content: &[u8]) -> Result<Vec<ReqContent>, serde_json::Error> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(content)?;
+
+    Ok(values.into_iter().filter_map(decode_entry).collect())
+}
+
+/// Validates a single mailbox entry, the same way `decode_mailbox` does for
+/// every element of the mailbox array.
+/// 
+/// # Returns
+/// 
+/// The entry, an `Error` entry when it is invalid but carries an id, or `None`
+/// when it has no usable id.
+pub fn decode_entry(value: serde_json::Value) -> Option<ReqContent> {
+    let id = value["head"]["id"].as_str().map(String::from);
+    let timestamp = value["head"]["timestamp"].as_i64().unwrap_or_default();
+
+    match serde_json::from_value::<ReqContent>(value) {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            eprintln!("跳过无效的数据：{:?} {}", id, err);
+
+            let err = TransError::new(ErrorCode::BadRequest, format!("invalid entry: {}", err));
+
+            id.map(|id| ReqContent {
+                head: TransHead {
+                    id,
+                    content_type: Some(String::from("error")),
+                    state: State::Error,
+                    timestamp,
+                    ..Default::default()
+                },
+                body: serde_json::to_string(&err).unwrap_or(err.message),
+                payload: None,
+            })
+        }
+    }
 }
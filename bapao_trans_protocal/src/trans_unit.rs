@@ -1,142 +1,344 @@
-use crate::trans_content::{
-    ReqContent, ResContentType, ResFileContent, ResStringContent, TransHead, TransUnitType,
-};
-
-/// Represents a single request/response transaction unit.
-/// 
-/// Each `TransUnit` encapsulates one request from an external client and provides
-/// methods to access the request data and create properly formatted responses.
-/// 
-/// # Usage Flow
-/// 
-/// 1. Create from incoming `ReqContent` using `new()`
-/// 2. Get request data using `get()` 
-/// 3. Process the request in your application logic
-/// 4. Create response using `set()` with your response data
-/// 
-/// # Examples
-/// 
-/// ```rust
-/// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
-/// 
-/// // Create from request
-/// let request = ReqContent { /* ... */ };
-/// let unit = TransUnit::new(request);
-/// 
-/// // Get request path
-/// let path = unit.get();
-/// 
-/// // Create response
-/// let response = unit.set(TransUnitType::String("Hello".to_string()));
-/// ```
-pub struct TransUnit {
-    content: ReqContent,
-}
-
-impl TransUnit {
-    /// Creates a new `TransUnit` from request content.
-    /// 
-    /// # Parameters
-    /// 
-    /// * `content` - The request content received from the transport layer
-    /// 
-    /// # Returns
-    /// 
-    /// A new `TransUnit` wrapping the request content
-    /// 
-    /// # Examples
-    /// 
-    /// ```rust
-    /// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
-    /// 
-    /// let request = ReqContent {
-    ///     head: TransHead {
-    ///         id: "req_123".to_string(),
-    ///         content_type: None,
-    ///         state: "Pending".to_string(),
-    ///         timestamp: 1234567890,
-    ///     },
-    ///     body: "/api/status".to_string(),
-    /// };
-    /// 
-    /// let unit = TransUnit::new(request);
-    /// ```
-    pub fn new(content: ReqContent) -> TransUnit {
-        return TransUnit { content: content };
-    }
-
-    /// Gets the request body content.
-    /// 
-    /// Returns a reference to the request body, which typically contains
-    /// the route path or command that the external client wants to execute.
-    /// 
-    /// # Returns
-    /// 
-    /// `&String` - Reference to the request body content
-    /// 
-    /// # Examples
-    /// 
-    /// ```rust
-    /// let unit = TransUnit::new(request_content);
-    /// let route = unit.get();
-    /// 
-    /// match route.as_str() {
-    ///     "/api/status" => handle_status(),
-    ///     "/api/data" => handle_data(),
-    ///     _ => handle_unknown(),
-    /// }
-    /// ```
-    pub fn get(&self) -> &String {
-        return &self.content.body;
-    }
-
-    /// Creates a response from the provided content, preserving request metadata.
-    /// 
-    /// This method takes your response data and wraps it in the proper response
-    /// format, copying the request ID and timestamp while updating the state to "Done".
-    /// 
-    /// # Parameters
-    /// 
-    /// * `content` - The response data to send back
-    /// 
-    /// # Returns
-    /// 
-    /// `ResContentType` - Properly formatted response ready for transmission
-    /// 
-    /// # Examples
-    /// 
-    /// ```rust
-    /// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::TransUnitType};
-    /// 
-    /// let unit = TransUnit::new(request_content);
-    /// 
-    /// // Create text response
-    /// let text_response = unit.set(TransUnitType::String("Success".to_string()));
-    /// 
-    /// // Create file response
-    /// let file_data = std::fs::read("image.jpg").unwrap();
-    /// let file_response = unit.set(TransUnitType::File(file_data));
-    /// ```
-    pub fn set(&self, content: TransUnitType) -> ResContentType {
-        match content {
-            TransUnitType::String(str) => ResContentType::String(ResStringContent {
-                head: TransHead {
-                    id: self.content.head.id.clone(),
-                    state: String::from("Done"),
-                    timestamp: self.content.head.timestamp,
-                    content_type: Option::Some(String::from("string")),
-                },
-                body: str,
-            }),
-            TransUnitType::File(str) => ResContentType::File(ResFileContent {
-                head: TransHead {
-                    id: self.content.head.id.clone(),
-                    state: String::from("Done"),
-                    timestamp: self.content.head.timestamp,
-                    content_type: Option::Some(String::from("file")),
-                },
-                body: str,
-            }),
-        }
-    }
-}
+use serde::de::DeserializeOwned;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::clock::{self, Clock};
+use crate::trans_content::{
+    ReqContent, ResContentType, ResFileContent, ResStringContent, State, TransHead, TransUnitType,
+};
+
+/// Everything a route handler gets to know about the request it serves.
+/// 
+/// Built by `TransUnit::context()` from the request entry: the route and
+/// query parameters are split out of the request body, the rest is copied
+/// from the request header and payload.
+/// 
+/// # Fields
+/// 
+/// * `id` - Unique identifier of the request
+/// * `timestamp` - Unix timestamp in milliseconds when the request was created
+/// * `content_type` - Content type declared by the client, if any
+/// * `route` - Route path without the query string (e.g. `/monitor/pic/shot`)
+/// * `query` - Decoded query parameters from the request body
+/// * `payload` - JSON payload sent along with the request
+/// * `headers` - Free-form metadata from the request header
+/// * `client_id` - Key id of the client whose signature was verified, `None`
+///   when the listener does not check signatures
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// use bapao_trans_protocal::{trans_content::TransUnitType, trans_unit::RequestContext};
+/// 
+/// fn shot(ctx: RequestContext) -> TransUnitType {
+///     let resolution = ctx.param("resolution").unwrap_or("1440x720");
+///     TransUnitType::String(format!("{} at {}", ctx.route, resolution))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub id: String,
+    pub timestamp: i64,
+    pub content_type: Option<String>,
+    pub route: String,
+    pub query: HashMap<String, String>,
+    pub payload: Option<serde_json::Value>,
+    pub headers: HashMap<String, String>,
+    pub client_id: Option<String>,
+}
+
+impl RequestContext {
+    /// Returns the query parameter `key`, if present.
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query.get(key).map(|value| &value[..])
+    }
+
+    /// Returns the header `key`, if present.
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(|value| &value[..])
+    }
+
+    /// Deserializes the JSON payload into `T`.
+    /// 
+    /// # Returns
+    /// 
+    /// `Ok(None)` when the request carries no payload, or the deserialization
+    /// error when the payload does not match `T`.
+    pub fn payload_as<T: DeserializeOwned>(&self) -> Result<Option<T>, serde_json::Error> {
+        match &self.payload {
+            Some(payload) => serde_json::from_value(payload.clone()).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Represents a single request/response transaction unit.
+/// 
+/// Each `TransUnit` encapsulates one request from an external client and provides
+/// methods to access the request data and create properly formatted responses.
+/// 
+/// # Usage Flow
+/// 
+/// 1. Create from incoming `ReqContent` using `new()`
+/// 2. Get the route using `route()`, or the full request data using `context()`
+/// 3. Process the request in your application logic
+/// 4. Create response using `set()` with your response data
+/// 
+/// # Examples
+/// 
+/// ```rust
+/// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
+/// 
+/// // Create from request
+/// let request = ReqContent {
+///     head: TransHead {
+///         id: "req_123".to_string(),
+///         ..Default::default()
+///     },
+///     body: "/api/hello?name=bapao".to_string(),
+///     payload: None,
+/// };
+/// let unit = TransUnit::new(request);
+/// 
+/// // Get request path
+/// assert_eq!(unit.route(), "/api/hello");
+/// 
+/// // Create response
+/// let response = unit.set(TransUnitType::String("Hello".to_string()));
+/// assert!(matches!(response, ResContentType::String(_)));
+/// ```
+pub struct TransUnit {
+    content: ReqContent,
+    client_id: Option<String>,
+    clock: Arc<dyn Clock>,
+}
+
+impl TransUnit {
+    /// Creates a new `TransUnit` from request content.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `content` - The request content received from the transport layer
+    /// 
+    /// # Returns
+    /// 
+    /// A new `TransUnit` wrapping the request content
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
+    /// 
+    /// let request = ReqContent {
+    ///     head: TransHead {
+    ///         id: "req_123".to_string(),
+    ///         content_type: None,
+    ///         state: State::Pending,
+    ///         timestamp: 1234567890,
+    ///         ..Default::default()
+    ///     },
+    ///     body: "/api/status".to_string(),
+    ///     payload: None,
+    /// };
+    /// 
+    /// let unit = TransUnit::new(request);
+    /// ```
+    pub fn new(content: ReqContent) -> TransUnit {
+        TransUnit {
+            content,
+            client_id: None,
+            clock: clock::system(),
+        }
+    }
+
+    /// 响应的时间戳由监听端的时钟生成
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> TransUnit {
+        self.clock = clock;
+        self
+    }
+
+    /// 记录验证过签名的客户端
+    pub(crate) fn with_client_id(mut self, client_id: String) -> TransUnit {
+        self.client_id = Some(client_id);
+        self
+    }
+
+    /// Gets the key id of the client that signed the request.
+    /// 
+    /// # Returns
+    /// 
+    /// `Some` only when the listener verified the signature against its trust
+    /// list, see `BtpListener::set_trust_list`.
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    /// Gets the request body content.
+    /// 
+    /// Returns a reference to the request body, which typically contains
+    /// the route path or command that the external client wants to execute.
+    /// 
+    /// # Returns
+    /// 
+    /// `&String` - Reference to the request body content
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
+    /// 
+    /// let unit = TransUnit::new(ReqContent {
+    ///     head: TransHead::default(),
+    ///     body: "/api/status".to_string(),
+    ///     payload: None,
+    /// });
+    /// 
+    /// let answer = match unit.get().as_str() {
+    ///     "/api/status" => "running",
+    ///     "/api/data" => "42",
+    ///     _ => "unknown route",
+    /// };
+    /// 
+    /// assert_eq!(answer, "running");
+    /// ```
+    pub fn get(&self) -> &String {
+        &self.content.body
+    }
+
+    /// Gets the route path of the request, without its query string.
+    /// 
+    /// # Returns
+    /// 
+    /// `&str` - The part of the request body before `?`
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
+    /// 
+    /// let unit = TransUnit::new(ReqContent {
+    ///     head: TransHead::default(),
+    ///     body: "/monitor/pic/shot?resolution=1440x720".to_string(),
+    ///     payload: None,
+    /// });
+    /// 
+    /// assert_eq!(unit.route(), "/monitor/pic/shot");
+    /// assert_eq!(unit.context().param("resolution"), Some("1440x720"));
+    /// ```
+    pub fn route(&self) -> &str {
+        match self.content.body.split_once('?') {
+            Some((route, _)) => route,
+            None => &self.content.body,
+        }
+    }
+
+    /// Builds the context handed to the route handler.
+    /// 
+    /// Query parameters are decoded from the part of the body after `?`; the
+    /// payload and headers are copied from the request.
+    /// 
+    /// # Returns
+    /// 
+    /// `RequestContext` - The request id, timestamp, content type, route,
+    /// query parameters, payload, headers and verified client id
+    pub fn context(&self) -> RequestContext {
+        let query = match self.content.body.split_once('?') {
+            Some((_, query)) => form_urlencoded::parse(query.as_bytes())
+                .into_owned()
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        RequestContext {
+            id: self.content.head.id.clone(),
+            timestamp: self.content.head.timestamp,
+            content_type: self.content.head.content_type.clone(),
+            route: self.route().to_string(),
+            query,
+            payload: self.content.payload.clone(),
+            headers: self.content.head.headers.clone(),
+            client_id: self.client_id.clone(),
+        }
+    }
+
+    /// Creates a response from the provided content, preserving request metadata.
+    /// 
+    /// This method takes your response data and wraps it in the proper response
+    /// format, copying the request ID while updating the state to `State::Done`,
+    /// or to `State::Error` for a `TransUnitType::Error`. The timestamp is the
+    /// time the response was created, taken from the listener's clock (see
+    /// `BtpListener::set_clock`), so the response stays in the mailbox for the
+    /// whole expiry window however long the request took.
+    /// 
+    /// # Parameters
+    /// 
+    /// * `content` - The response data to send back
+    /// 
+    /// # Returns
+    /// 
+    /// `ResContentType` - Properly formatted response ready for transmission
+    /// 
+    /// # Examples
+    /// 
+    /// ```rust
+    /// use bapao_trans_protocal::{trans_unit::TransUnit, trans_content::*};
+    /// 
+    /// let unit = TransUnit::new(ReqContent {
+    ///     head: TransHead {
+    ///         id: "req_123".to_string(),
+    ///         ..Default::default()
+    ///     },
+    ///     body: "/monitor/pic/shot".to_string(),
+    ///     payload: None,
+    /// });
+    /// 
+    /// // Create text response
+    /// let text_response = unit.set(TransUnitType::String("Success".to_string()));
+    /// 
+    /// // Create file response
+    /// let file_data = vec![0xff, 0xd8, 0xff, 0xe0];
+    /// let file_response = unit.set(TransUnitType::File(file_data));
+    /// 
+    /// match file_response {
+    ///     ResContentType::File(file) => assert_eq!(file.head.id, "req_123"),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn set(&self, content: TransUnitType) -> ResContentType {
+        let timestamp = self.clock.now().timestamp_millis();
+
+        match content {
+            TransUnitType::String(str) => ResContentType::String(ResStringContent {
+                head: TransHead {
+                    id: self.content.head.id.clone(),
+                    state: State::Done,
+                    timestamp,
+                    content_type: Option::Some(String::from("string")),
+                    ..Default::default()
+                },
+                body: str,
+                payload: None,
+            }),
+            TransUnitType::File(str) => ResContentType::File(ResFileContent {
+                head: TransHead {
+                    id: self.content.head.id.clone(),
+                    state: State::Done,
+                    timestamp,
+                    content_type: Option::Some(String::from("file")),
+                    ..Default::default()
+                },
+                body: str,
+            }),
+            TransUnitType::Error(err) => ResContentType::String(ResStringContent {
+                head: TransHead {
+                    id: self.content.head.id.clone(),
+                    state: State::Error,
+                    timestamp,
+                    content_type: Option::Some(String::from("error")),
+                    ..Default::default()
+                },
+                body: serde_json::to_string(&err).unwrap_or(err.message),
+                payload: None,
+            }),
+        }
+    }
+}
@@ -1,60 +1,209 @@
-use chrono::{Duration, TimeZone, Utc};
-
-use crate::trans_content::ReqContent;
-
-/// Removes expired requests from the content list.
-/// 
-/// Filters out requests that are older than 30 minutes to prevent
-/// processing of stale requests and to keep the communication channel clean.
-/// 
-/// # Parameters
-/// 
-/// * `contents` - Vector of request content to filter
-/// 
-/// # Returns
-/// 
-/// `Vec<ReqContent>` - Filtered vector containing only non-expired requests
-/// 
-/// # Expiration Logic
-/// 
-/// A request is considered expired if:
-/// `current_time - request_timestamp > 30 minutes`
-/// 
-/// # Examples
-/// 
-/// ```rust
-/// use bapao_trans_protocal::{utils::trim_expired_data, trans_content::*};
-/// 
-/// let all_requests = vec![
-///     // Mix of recent and old requests
-/// ];
-/// 
-/// let active_requests = trim_expired_data(all_requests);
-/// println!("Filtered to {} active requests", active_requests.len());
-/// ```
-/// 
-/// # Performance
-/// 
-/// This function operates in O(n) time where n is the number of requests.
-/// It's called automatically by the transport layer to maintain system hygiene.
-pub fn trim_expired_data(contents: Vec<ReqContent>) -> Vec<ReqContent> {
-    contents
-        .into_iter()
-        .filter(|item| {
-            // start + exp > now  === 过期
-            // start > now - exp  === 过期
-            // now - exp < start  === 过期
-            // limit = now - exp;
-            // limit.lt(start)    === 过期
-
-            // 过期时间
-            let duration = Duration::minutes(30);
-
-            let limit_time_stamp = Utc::now().checked_sub_signed(duration);
-
-            let start_time_stamp = Utc.timestamp_millis(item.head.timestamp);
-
-            return limit_time_stamp.lt(&Option::Some(start_time_stamp));
-        })
-        .collect()
-}
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use crate::trans_content::{ReqContent, State};
+
+/// 请求的有效期（分钟），超过后不再处理
+pub(crate) const EXPIRY_MINUTES: i64 = 30;
+
+/// Expires stale requests and removes stale entries from the content list.
+/// 
+/// Keeps the communication channel clean and prevents processing of stale
+/// requests, while leaving clients some time to see what happened to theirs.
+/// 
+/// # Parameters
+/// 
+/// * `contents` - Vector of request content to filter
+/// * `now` - Current time, see `clock::Clock`
+/// * `expiry` - How long a request stays valid, 30 minutes by default
+/// 
+/// # Returns
+/// 
+/// `Vec<ReqContent>` - The remaining entries, with stale requests marked `Expired`
+/// 
+/// # Expiration Logic
+/// 
+/// With `age = now - entry_timestamp`:
+/// 
+/// - `Pending` and `Processing` requests older than `expiry` become `Expired`
+/// - `Done`, `Error` and `Cancelled` entries older than `expiry` are removed;
+///   responses carry the time they were produced, see `TransUnit::set`
+/// - `Expired` entries are removed once older than twice `expiry`, so their
+///   clients get another `expiry` to notice the expiry
+/// - Entries whose timestamp is out of range count as expired
+/// 
+/// # Examples
+/// 
+/// The module is internal to the crate, so the example is not compiled;
+/// `BtpListener::accept` trims the mailbox this way on every cycle.
+/// 
+/// ```rust,ignore
+/// use crate::{utils::trim_expired_data, trans_content::*};
+/// use chrono::{Duration, Utc};
+/// 
+/// let now = Utc::now();
+/// let old = ReqContent {
+///     head: TransHead {
+///         id: "req_001".to_string(),
+///         timestamp: (now - Duration::minutes(45)).timestamp_millis(),
+///         ..Default::default()
+///     },
+///     body: "/monitor/pic/shot".to_string(),
+///     payload: None,
+/// };
+/// 
+/// let active_requests = trim_expired_data(vec![old], now, Duration::minutes(30));
+/// assert_eq!(active_requests[0].head.state, State::Expired);
+/// ```
+/// 
+/// # Performance
+/// 
+/// This function operates in O(n) time where n is the number of requests.
+/// It's called automatically by the transport layer to maintain system hygiene.
+pub fn trim_expired_data(
+    contents: Vec<ReqContent>,
+    now: DateTime<Utc>,
+    expiry: Duration,
+) -> Vec<ReqContent> {
+    contents
+        .into_iter()
+        .filter_map(|mut item| {
+            // start + exp > now  === 过期
+            // start > now - exp  === 过期
+            // now - exp < start  === 过期
+            // limit = now - exp;
+            // limit.lt(start)    === 过期
+
+            // 时间戳由客户端写入，超出范围的按已过期处理
+            let start_time_stamp = Utc.timestamp_millis_opt(item.head.timestamp).single();
+
+            let is_expired = |exp: Duration| match start_time_stamp {
+                Some(start_time_stamp) => {
+                    let limit_time_stamp = now.checked_sub_signed(exp);
+
+                    !limit_time_stamp.lt(&Option::Some(start_time_stamp))
+                }
+                None => true,
+            };
+
+            match item.head.state {
+                State::Pending | State::Processing if is_expired(expiry) => {
+                    item.head.state = State::Expired;
+                    Some(item)
+                }
+                State::Expired if is_expired(expiry * 2) => None,
+                State::Done | State::Error | State::Cancelled if is_expired(expiry) => None,
+                _ => Some(item),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trans_content::TransHead;
+
+    fn entry(id: &str, state: State, timestamp: i64) -> ReqContent {
+        ReqContent {
+            head: TransHead {
+                id: id.to_string(),
+                state,
+                timestamp,
+                ..Default::default()
+            },
+            body: "/ping".to_string(),
+            payload: None,
+        }
+    }
+
+    fn trim(contents: Vec<ReqContent>, now: DateTime<Utc>) -> Vec<(String, State)> {
+        trim_expired_data(contents, now, Duration::minutes(EXPIRY_MINUTES))
+            .into_iter()
+            .map(|item| (item.head.id, item.head.state))
+            .collect()
+    }
+
+    #[test]
+    fn stale_requests_are_marked_expired() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        let old = (now - Duration::minutes(31)).timestamp_millis();
+        let fresh = (now - Duration::minutes(5)).timestamp_millis();
+
+        assert_eq!(
+            trim(
+                vec![
+                    entry("old", State::Pending, old),
+                    entry("taken", State::Processing, old),
+                    entry("new", State::Pending, fresh),
+                ],
+                now
+            ),
+            vec![
+                ("old".to_string(), State::Expired),
+                ("taken".to_string(), State::Expired),
+                ("new".to_string(), State::Pending),
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_responses_are_removed() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        let old = (now - Duration::minutes(31)).timestamp_millis();
+        let fresh = (now - Duration::minutes(5)).timestamp_millis();
+
+        assert_eq!(
+            trim(
+                vec![
+                    entry("done", State::Done, old),
+                    entry("failed", State::Error, old),
+                    entry("cancelled", State::Cancelled, old),
+                    entry("recent", State::Done, fresh),
+                ],
+                now
+            ),
+            vec![("recent".to_string(), State::Done)]
+        );
+    }
+
+    #[test]
+    fn expired_entries_are_kept_for_another_window() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+        let once = (now - Duration::minutes(45)).timestamp_millis();
+        let twice = (now - Duration::minutes(61)).timestamp_millis();
+
+        assert_eq!(
+            trim(
+                vec![
+                    entry("kept", State::Expired, once),
+                    entry("gone", State::Expired, twice),
+                ],
+                now
+            ),
+            vec![("kept".to_string(), State::Expired)]
+        );
+    }
+
+    #[test]
+    fn out_of_range_timestamps_count_as_expired() {
+        let now = Utc.ymd(2024, 3, 1).and_hms(12, 0, 0);
+
+        // 不能让客户端写入的时间戳使监听端 panic
+        assert_eq!(
+            trim(
+                vec![
+                    entry("huge", State::Pending, i64::MAX / 2),
+                    entry("tiny", State::Processing, i64::MIN),
+                    entry("done", State::Done, i64::MAX),
+                ],
+                now
+            ),
+            vec![
+                ("huge".to_string(), State::Expired),
+                ("tiny".to_string(), State::Expired),
+            ]
+        );
+
+        assert!(trim(vec![entry("huge", State::Expired, i64::MAX / 2)], now).is_empty());
+    }
+}
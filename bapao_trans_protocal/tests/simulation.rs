@@ -0,0 +1,388 @@
+use async_trait::async_trait;
+use bapao_trans_protocal::{
+    backend::{self, MailboxBackend, MemoryBackend, RevisionConflict},
+    clock::{Clock, ManualClock},
+    error::BtpError,
+    replay::ReplayGuard,
+    retry::{RateBudget, RetryPolicy},
+    scheduler::{PollSchedule, PollScheduler, QuietHours},
+    trans_content::{ReqContent, State, TransHead, TransUnitType},
+    trans_unit::TransUnit,
+    BtpListener,
+};
+use chrono::{Local, NaiveTime, TimeZone, Utc};
+use std::{
+    error::Error,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn clock() -> ManualClock {
+    ManualClock::new(Utc.ymd(2024, 3, 1).and_hms(12, 0, 0))
+}
+
+fn listener(backend: &MemoryBackend, clock: &ManualClock) -> BtpListener<MemoryBackend> {
+    let mut listener = BtpListener::with_backend(backend.clone());
+
+    listener.set_clock(Arc::new(clock.clone()));
+    listener
+}
+
+fn request(id: &str, clock: &ManualClock) -> ReqContent {
+    ReqContent {
+        head: TransHead {
+            id: id.to_string(),
+            state: State::Pending,
+            timestamp: clock.now().timestamp_millis(),
+            ..Default::default()
+        },
+        body: "/ping".to_string(),
+        payload: None,
+    }
+}
+
+/// 模拟客户端向 mailbox 写入请求
+async fn send(backend: &MemoryBackend, request: ReqContent) {
+    backend::update_mailbox(backend, |mut content| {
+        content.push(request.clone());
+        Ok((Some(content), ()))
+    })
+    .await
+    .unwrap();
+}
+
+async fn states(backend: &MemoryBackend) -> Vec<(String, State)> {
+    let (content, _) = backend.read_mailbox().await.unwrap();
+
+    content
+        .into_iter()
+        .map(|item| (item.head.id, item.head.state))
+        .collect()
+}
+
+fn ids(units: &[TransUnit]) -> Vec<String> {
+    units.iter().map(|unit| unit.context().id).collect()
+}
+
+/// 模拟其他客户端频繁写入：前 `conflicts` 次写入 mailbox 都会冲突
+struct BusyBackend {
+    inner: MemoryBackend,
+    conflicts: AtomicUsize,
+}
+
+#[async_trait]
+impl MailboxBackend for BusyBackend {
+    async fn read_mailbox(&self) -> Result<(Vec<ReqContent>, String), Box<dyn Error + Send + Sync>> {
+        self.inner.read_mailbox().await
+    }
+
+    async fn write_mailbox(
+        &self,
+        content: &[ReqContent],
+        revision: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let conflicted = self
+            .conflicts
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+            .is_ok();
+
+        if conflicted {
+            return Err(Box::new(RevisionConflict));
+        }
+
+        self.inner.write_mailbox(content, revision).await
+    }
+
+    async fn put_blob(&self, name: &str, content: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.inner.put_blob(name, content).await
+    }
+
+    async fn get_blob(&self, name: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.inner.get_blob(name).await
+    }
+
+    async fn delete_blob(&self, name: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.inner.delete_blob(name).await
+    }
+}
+
+#[tokio::test]
+async fn requests_expire_after_the_window() {
+    let clock = clock();
+    let backend = MemoryBackend::new();
+    let mut listener = listener(&backend, &clock);
+
+    send(&backend, request("old", &clock)).await;
+
+    clock.advance(31 * MINUTE);
+    send(&backend, request("new", &clock)).await;
+
+    // 过期的请求不再分发，标记为 Expired 写回
+    let accepted = listener.accept().await.unwrap();
+    assert_eq!(ids(&accepted), vec!["new".to_string()]);
+    assert_eq!(
+        states(&backend).await,
+        vec![
+            ("old".to_string(), State::Expired),
+            ("new".to_string(), State::Processing),
+        ]
+    );
+
+    // Expired 的请求再保留一个有效期，让客户端看到结果
+    clock.advance(30 * MINUTE);
+    listener.stash(accepted[0].set(TransUnitType::String("pong".to_string())));
+    listener.accept().await.unwrap();
+
+    assert_eq!(
+        states(&backend).await,
+        vec![("new".to_string(), State::Done)]
+    );
+}
+
+#[tokio::test]
+async fn expiry_is_written_back_without_new_requests() {
+    let clock = clock();
+    let backend = MemoryBackend::new();
+    let mut listener = listener(&backend, &clock);
+
+    send(&backend, request("lost", &clock)).await;
+
+    // 没有可处理的请求，但过期的标记仍然写回
+    clock.advance(31 * MINUTE);
+    assert!(listener.accept().await.unwrap().is_empty());
+    assert_eq!(
+        states(&backend).await,
+        vec![("lost".to_string(), State::Expired)]
+    );
+
+    // 没有任何变化时不写入
+    let (_, revision) = backend.read_mailbox().await.unwrap();
+    listener.accept().await.unwrap();
+    assert_eq!(backend.read_mailbox().await.unwrap().1, revision);
+
+    // 再过一个有效期之后移除
+    clock.advance(30 * MINUTE);
+    listener.accept().await.unwrap();
+    assert!(states(&backend).await.is_empty());
+}
+
+#[tokio::test]
+async fn responses_are_kept_for_a_whole_window() {
+    let clock = clock();
+    let backend = MemoryBackend::new();
+    let mut listener = listener(&backend, &clock);
+
+    send(&backend, request("slow", &clock)).await;
+
+    let accepted = listener.accept().await.unwrap();
+
+    // 处理耗时 20 分钟，响应的时间戳是生成响应的时间
+    clock.advance(20 * MINUTE);
+    let response = accepted[0].set(TransUnitType::String("done".to_string()));
+    listener.stash(response);
+    listener.accept().await.unwrap();
+
+    let (content, _) = backend.read_mailbox().await.unwrap();
+    assert_eq!(content[0].head.timestamp, clock.now().timestamp_millis());
+
+    // 距离请求已超过 30 分钟，但响应还没有过期
+    clock.advance(20 * MINUTE);
+    send(&backend, request("next", &clock)).await;
+    listener.accept().await.unwrap();
+
+    assert_eq!(
+        states(&backend).await,
+        vec![
+            ("slow".to_string(), State::Done),
+            ("next".to_string(), State::Processing),
+        ]
+    );
+
+    clock.advance(15 * MINUTE);
+    send(&backend, request("last", &clock)).await;
+    listener.accept().await.unwrap();
+
+    let remaining: Vec<String> = states(&backend)
+        .await
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    assert_eq!(remaining, vec!["next".to_string(), "last".to_string()]);
+}
+
+#[tokio::test]
+async fn expiry_window_can_be_shortened() {
+    let clock = clock();
+    let backend = MemoryBackend::new();
+    let mut listener = listener(&backend, &clock);
+
+    listener.set_expiry(5 * MINUTE);
+
+    send(&backend, request("stale", &clock)).await;
+    clock.advance(6 * MINUTE);
+    send(&backend, request("fresh", &clock)).await;
+
+    let accepted = listener.accept().await.unwrap();
+
+    assert_eq!(ids(&accepted), vec!["fresh".to_string()]);
+    assert_eq!(
+        states(&backend).await[0],
+        ("stale".to_string(), State::Expired)
+    );
+}
+
+#[tokio::test]
+async fn replay_guard_follows_the_clock() {
+    let clock = clock();
+    let mut guard = ReplayGuard::in_memory().with_clock(Arc::new(clock.clone()));
+
+    let first = request("req_001", &clock);
+
+    assert!(guard.check(&first).is_ok());
+    assert!(guard.check(&first).is_err());
+
+    // 时间戳超出允许的时钟偏差
+    let mut ahead = request("req_002", &clock);
+    ahead.head.timestamp += chrono::Duration::minutes(10).num_milliseconds();
+    assert!(guard.check(&ahead).is_err());
+
+    // 超过有效期之后按过期拒绝
+    clock.advance(30 * MINUTE);
+    let err = guard.check(&first).unwrap_err();
+    assert_eq!(err.message, "request is older than the expiry window");
+}
+
+#[tokio::test]
+async fn retries_back_off_without_sleeping() {
+    let clock = clock();
+    let start = clock.now();
+    let mut calls = 0;
+
+    let result: Result<(), BtpError> = RetryPolicy::default()
+        .run_with_clock(&clock, || {
+            calls += 1;
+            async {
+                Err(BtpError::Network {
+                    status: Some(503),
+                    message: "Service Unavailable".to_string(),
+                })
+            }
+        })
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(calls, 4);
+
+    // 第 n 次重试等待 initial_delay * 2^(n-1) 的 50% 到 100%
+    let slept = clock.slept();
+    assert_eq!(slept.len(), 3);
+
+    for (retry, delay) in slept.iter().enumerate() {
+        let full = Duration::from_secs(1 << retry);
+
+        assert!(*delay >= full / 2 && *delay <= full, "{:?}", delay);
+    }
+
+    let total: Duration = slept.iter().sum();
+    assert_eq!((clock.now() - start).to_std().unwrap(), total);
+}
+
+#[tokio::test]
+async fn rate_limit_pacing_follows_the_clock() {
+    let clock = clock();
+
+    let budget = RateBudget {
+        limit: Some(100),
+        remaining: 10,
+        reset: Some((clock.now() + chrono::Duration::minutes(50)).timestamp()),
+    };
+
+    // 剩余 10 次调用，分摊到重置前的 50 分钟
+    assert_eq!(budget.pacing_at(clock.now()), 5 * MINUTE);
+
+    clock.advance(40 * MINUTE);
+    assert_eq!(budget.pacing_at(clock.now()), MINUTE);
+}
+
+#[tokio::test]
+async fn polling_pauses_during_quiet_hours() {
+    let late = Local.ymd(2024, 3, 1).and_hms(23, 0, 0);
+    let clock = ManualClock::new(late.with_timezone(&Utc));
+
+    let mut scheduler = PollScheduler::new(PollSchedule {
+        quiet_hours: Some(QuietHours::new(
+            NaiveTime::from_hms(22, 0, 0),
+            NaiveTime::from_hms(7, 0, 0),
+        )),
+        ..Default::default()
+    })
+    .with_clock(Arc::new(clock.clone()));
+
+    // 一直等到早上 7 点，之后从最小间隔重新开始退避
+    let delay = scheduler.next_delay(false);
+    assert_eq!(delay, Duration::from_secs(8 * 60 * 60));
+
+    clock.sleep(delay).await;
+    assert_eq!(clock.local_now().time(), NaiveTime::from_hms(7, 0, 0));
+
+    assert_eq!(scheduler.next_delay(false), Duration::from_secs(20));
+    assert_eq!(scheduler.next_delay(true), Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn mailbox_conflicts_back_off_on_the_clock() {
+    let clock = clock();
+    let inner = MemoryBackend::new();
+
+    send(&inner, request("busy", &clock)).await;
+
+    let mut listener = BtpListener::with_backend(BusyBackend {
+        inner: inner.clone(),
+        conflicts: AtomicUsize::new(3),
+    });
+    listener.set_clock(Arc::new(clock.clone()));
+
+    // 每次冲突之后重新读取，等待时间翻倍
+    let accepted = listener.accept().await.unwrap();
+
+    assert_eq!(ids(&accepted), vec!["busy".to_string()]);
+    assert_eq!(
+        clock.slept(),
+        vec![
+            Duration::from_millis(500),
+            Duration::from_secs(1),
+            Duration::from_secs(2),
+        ]
+    );
+    assert_eq!(
+        states(&inner).await,
+        vec![("busy".to_string(), State::Processing)]
+    );
+}
+
+#[tokio::test]
+async fn mailbox_conflicts_give_up_after_the_last_attempt() {
+    let clock = clock();
+    let backend = BusyBackend {
+        inner: MemoryBackend::new(),
+        conflicts: AtomicUsize::new(usize::MAX),
+    };
+
+    let result = backend::update_mailbox_with_clock(&backend, &clock, |mut content| {
+        content.push(request("lost", &clock));
+        Ok((Some(content), ()))
+    })
+    .await;
+
+    assert!(result.unwrap_err().downcast_ref::<RevisionConflict>().is_some());
+    assert_eq!(clock.slept().len(), 4);
+    assert_eq!(
+        clock.slept().iter().sum::<Duration>(),
+        Duration::from_millis(7500)
+    );
+}
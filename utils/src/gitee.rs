@@ -0,0 +1,829 @@
+use bapao_trans_protocal::config::BapaoConfig;
+use chrono::Utc;
+use hyper::{
+    body,
+    header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::task::JoinHandle;
+
+/// Access token the mock server accepts.
+pub const TOKEN: &str = "mock-token";
+
+/// Owner of the mock repository.
+pub const OWNER: &str = "bapao";
+
+/// Name of the mock repository.
+pub const REPO: &str = "mailbox";
+
+/// The only branch of the mock repository.
+pub const BRANCH: &str = "master";
+
+/// Path of the mailbox file, created empty when the server starts.
+pub const MAILBOX: &str = "io";
+
+/// A failure the mock server answers matching requests with, instead of
+/// handling them.
+///
+/// # Fields
+///
+/// * `method` - HTTP method to fail (`"GET"`, `"PUT"`, ...), `None` for all
+/// * `path` - File path in the repository to fail (e.g. `"io"`), `None` for all
+/// * `status` - HTTP status of the failure
+/// * `body` - Message returned in the JSON body
+/// * `times` - Number of matching requests to fail
+/// * `after_handling` - Handle the request first and only then answer with the
+///   failure, like a write that times out after the server stored it
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_test_utils::{Fault, MAILBOX};
+///
+/// // the next two writes of the mailbox fail as if the sha were stale
+/// let fault = Fault {
+///     method: Some("PUT".to_string()),
+///     path: Some(MAILBOX.to_string()),
+///     times: 2,
+///     ..Fault::new(409)
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fault {
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub status: u16,
+    pub body: String,
+    pub times: usize,
+    pub after_handling: bool,
+}
+
+impl Fault {
+    /// Fails the next request, whatever it is, with `status`.
+    pub fn new(status: u16) -> Self {
+        let body = StatusCode::from_u16(status)
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or("injected failure")
+            .to_string();
+
+        Fault {
+            method: None,
+            path: None,
+            status,
+            body,
+            times: 1,
+            after_handling: false,
+        }
+    }
+
+    fn matches(&self, method: &Method, path: Option<&str>) -> bool {
+        self.times > 0
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|fault_method| fault_method.eq_ignore_ascii_case(method.as_str()))
+            && self
+                .path
+                .as_ref()
+                .is_none_or(|fault_path| Some(fault_path.as_str()) == path)
+    }
+}
+
+/// A request received by the mock server.
+///
+/// # Fields
+///
+/// * `method` - HTTP method, e.g. `"PUT"`
+/// * `path` - File path in the repository, `None` for requests outside the
+///   contents API
+/// * `status` - HTTP status the request was answered with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: Option<String>,
+    pub status: u16,
+}
+
+/// 简单的限流窗口，和 gitee 一样按调用次数计算
+#[derive(Debug)]
+struct RateWindow {
+    limit: u64,
+    remaining: u64,
+    reset: i64,
+    window: Duration,
+}
+
+impl RateWindow {
+    fn new(limit: u64, window: Duration) -> Self {
+        RateWindow {
+            limit,
+            remaining: limit,
+            reset: Utc::now().timestamp() + window.as_secs().max(1) as i64,
+            window,
+        }
+    }
+
+    /// 窗口结束后恢复额度；额度用完时返回 false
+    fn take(&mut self) -> bool {
+        if Utc::now().timestamp() >= self.reset {
+            *self = RateWindow::new(self.limit, self.window);
+        }
+
+        if self.remaining == 0 {
+            return false;
+        }
+
+        self.remaining -= 1;
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Repo {
+    files: BTreeMap<String, Vec<u8>>,
+    faults: Vec<Fault>,
+    rate: Option<RateWindow>,
+    requests: Vec<RecordedRequest>,
+    commits: u64,
+    /// 分支当前指向的 commit
+    head: String,
+    /// Git Data API 创建的对象，按 sha 保存
+    blobs: HashMap<String, Vec<u8>>,
+    trees: HashMap<String, BTreeMap<String, Vec<u8>>>,
+    git_commits: HashMap<String, GitCommit>,
+}
+
+#[derive(Debug)]
+struct GitCommit {
+    tree: String,
+    parents: Vec<String>,
+}
+
+/// Local HTTP server emulating the Gitee contents API of one repository.
+///
+/// Serves `GET`, `POST`, `PUT` and `DELETE` on
+/// `/api/v5/repos/{OWNER}/{REPO}/contents/{path}` the way Gitee does:
+///
+/// * `GET` returns a file with its base64 content and sha, or the entries of
+///   a directory; a missing path is answered with 404
+/// * `POST` creates a file, and fails with 400 when it already exists
+/// * `PUT` and `DELETE` need the current `sha` of the file, and fail with 409
+///   when it is stale
+///
+/// The sha is the git blob id of the content, so it changes with every
+/// write. Requests without `Authorization: token {TOKEN}` are answered with
+/// 401, and requests for another branch than `BRANCH` with 404.
+///
+/// The Git Data API used by `GiteeBackend::with_atomic_commits` is emulated
+/// as far as the backend needs it: `GET branches/{BRANCH}`, `POST git/blobs`,
+/// `git/trees` and `git/commits`, and `PATCH git/refs/heads/{BRANCH}`. The
+/// ref is only updated when the new commit is a child of the current head,
+/// and answered with 422 otherwise. Every write through the contents API
+/// moves the head as well, and `ref` may name the head commit.
+///
+/// Failures are injected with `inject`, and the rate limit of the Gitee API
+/// is simulated with `set_rate_limit`. The server stops when the `MockGitee`
+/// is dropped.
+///
+/// # Examples
+///
+/// ```rust
+/// use bapao_test_utils::MockGitee;
+/// use bapao_trans_protocal::backend::{GiteeBackend, MailboxBackend};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let gitee = MockGitee::start().await;
+///     let backend = GiteeBackend::with_config(gitee.config());
+///
+///     let (content, sha) = backend.read_mailbox().await.unwrap();
+///
+///     assert!(content.is_empty());
+///     assert_eq!(Some(sha), gitee.sha("io"));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MockGitee {
+    addr: SocketAddr,
+    repo: Arc<Mutex<Repo>>,
+    server: JoinHandle<()>,
+}
+
+impl MockGitee {
+    /// Starts a server on a free local port, with an empty mailbox (`[]`)
+    /// at `MAILBOX`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when no local port can be bound, or outside a tokio runtime.
+    pub async fn start() -> Self {
+        let mut files = BTreeMap::new();
+
+        files.insert(MAILBOX.to_string(), b"[]".to_vec());
+
+        let repo = Arc::new(Mutex::new(Repo {
+            files,
+            faults: vec![],
+            rate: None,
+            requests: vec![],
+            commits: 0,
+            head: commit_sha(0),
+            blobs: HashMap::new(),
+            trees: HashMap::new(),
+            git_commits: HashMap::new(),
+        }));
+
+        let service_repo = repo.clone();
+
+        let make_service = make_service_fn(move |_conn| {
+            let repo = service_repo.clone();
+
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(repo.clone(), req))) }
+        });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let addr = server.local_addr();
+
+        let server = tokio::spawn(async move {
+            if let Err(err) = server.await {
+                eprintln!("mock gitee 出错：{}", err);
+            }
+        });
+
+        MockGitee { addr, repo, server }
+    }
+
+    /// Returns the API base URL of the server, for `api_base` in the config.
+    pub fn api_base(&self) -> String {
+        format!("http://{}/api/v5", self.addr)
+    }
+
+    /// Returns a config pointing at the mock repository, with `MAILBOX` as
+    /// the mailbox file.
+    pub fn config(&self) -> BapaoConfig {
+        BapaoConfig::parse(
+            &json!({
+                "access_token": TOKEN,
+                "user_name": OWNER,
+                "repo": REPO,
+                "file_path": MAILBOX,
+                "branch": BRANCH,
+                "api_base": self.api_base(),
+            })
+            .to_string(),
+        )
+        .unwrap()
+    }
+
+    /// Creates or replaces the file at `path`.
+    pub fn set_file(&self, path: &str, content: impl Into<Vec<u8>>) {
+        self.repo
+            .lock()
+            .unwrap()
+            .files
+            .insert(path.to_string(), content.into());
+    }
+
+    /// Returns the content of the file at `path`, if it exists.
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.repo.lock().unwrap().files.get(path).cloned()
+    }
+
+    /// Returns the sha of the file at `path`, if it exists.
+    pub fn sha(&self, path: &str) -> Option<String> {
+        self.file(path).map(|content| blob_sha(&content))
+    }
+
+    /// Returns the paths of all files in the repository.
+    pub fn files(&self) -> Vec<String> {
+        self.repo.lock().unwrap().files.keys().cloned().collect()
+    }
+
+    /// Fails the requests matching `fault`, see `Fault`.
+    ///
+    /// Faults are checked in the order they were injected; a request is
+    /// failed by the first fault matching it.
+    pub fn inject(&self, fault: Fault) {
+        self.repo.lock().unwrap().faults.push(fault);
+    }
+
+    /// Allows `limit` requests per `window`, like the Gitee API does per
+    /// access token.
+    ///
+    /// Every response then carries `X-RateLimit-Limit`,
+    /// `X-RateLimit-Remaining` and `X-RateLimit-Reset`. Once the budget is
+    /// used up, requests are answered with 403 "Rate limit exceeded" and a
+    /// `Retry-After` header until the window resets.
+    pub fn set_rate_limit(&self, limit: u64, window: Duration) {
+        self.repo.lock().unwrap().rate = Some(RateWindow::new(limit, window));
+    }
+
+    /// Returns the requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.repo.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockGitee {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// git 对文件内容计算的 blob sha，gitee 返回的 sha 就是这个值
+fn blob_sha(content: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content);
+
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn json_response(status: StatusCode, body: Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body.to_string()));
+
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/json;charset=UTF-8"),
+    );
+
+    resp
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, json!({ "message": message }))
+}
+
+async fn handle(repo: Arc<Mutex<Repo>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let prefix = format!("/api/v5/repos/{}/{}/contents/", OWNER, REPO);
+    let git_prefix = format!("/api/v5/repos/{}/{}/", OWNER, REPO);
+
+    let method = req.method().clone();
+    let path = req
+        .uri()
+        .path()
+        .strip_prefix(prefix.as_str())
+        .map(|path| path.trim_matches('/').to_string());
+    // contents API 之外的请求，例如 git/trees
+    let git_path = req
+        .uri()
+        .path()
+        .strip_prefix(git_prefix.as_str())
+        .map(|path| path.trim_matches('/').to_string());
+    let query: HashMap<String, String> =
+        form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect();
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        == Some(format!("token {}", TOKEN).as_str());
+
+    let body = match body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, &err.to_string())),
+    };
+
+    let mut repo = repo.lock().unwrap();
+
+    let mut resp = if !authorized {
+        error_response(
+            StatusCode::UNAUTHORIZED,
+            "401 Unauthorized: Access token does not exist",
+        )
+    } else if !repo.rate.as_mut().is_none_or(RateWindow::take) {
+        let mut resp = error_response(StatusCode::FORBIDDEN, "Rate limit exceeded");
+        let reset = repo.rate.as_ref().map_or(0, |rate| rate.reset);
+        let retry_after = (reset - Utc::now().timestamp()).max(1);
+
+        resp.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        resp
+    } else if let Some(fault) = repo
+        .faults
+        .iter_mut()
+        .find(|fault| fault.matches(&method, path.as_deref()))
+    {
+        fault.times -= 1;
+
+        let status =
+            StatusCode::from_u16(fault.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let resp = error_response(status, &fault.body);
+
+        // 请求照常处理，只是客户端收到的是错误
+        if fault.after_handling {
+            if let Some(path) = &path {
+                route(&mut repo, &method, path, &query, &body);
+            } else if let Some(git_path) = &git_path {
+                git_route(&mut repo, &method, git_path, &body);
+            }
+        }
+
+        resp
+    } else {
+        match (&path, &git_path) {
+            (Some(path), _) => route(&mut repo, &method, path, &query, &body),
+            (None, Some(git_path)) => git_route(&mut repo, &method, git_path, &body),
+            (None, None) => error_response(StatusCode::NOT_FOUND, "Not Found Project"),
+        }
+    };
+
+    if let Some(rate) = &repo.rate {
+        let headers = resp.headers_mut();
+
+        headers.insert("X-RateLimit-Limit", HeaderValue::from(rate.limit));
+        headers.insert("X-RateLimit-Remaining", HeaderValue::from(rate.remaining));
+        headers.insert("X-RateLimit-Reset", HeaderValue::from(rate.reset));
+    }
+
+    repo.requests.push(RecordedRequest {
+        method: method.to_string(),
+        path,
+        status: resp.status().as_u16(),
+    });
+
+    Ok(resp)
+}
+
+fn route(
+    repo: &mut Repo,
+    method: &Method,
+    path: &str,
+    query: &HashMap<String, String>,
+    body: &[u8],
+) -> Response<Body> {
+    // 写入的参数在 JSON 中，删除的参数在 query 中
+    let params: HashMap<String, String> = match method {
+        &Method::POST | &Method::PUT => match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Object(params)) => params
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, value.as_str()?.to_string())))
+                .collect(),
+            _ => return error_response(StatusCode::BAD_REQUEST, "body must be a JSON object"),
+        },
+        _ => query.clone(),
+    };
+
+    let branch = match method {
+        &Method::GET => params.get("ref"),
+        _ => params.get("branch"),
+    };
+
+    // 读取时 ref 也可以是分支当前指向的 commit
+    if branch.is_some_and(|branch| branch != BRANCH && *branch != repo.head) {
+        return error_response(StatusCode::NOT_FOUND, "Branch Not Found");
+    }
+
+    match *method {
+        Method::GET => get(repo, path),
+        Method::POST => create(repo, path, &params),
+        Method::PUT => update(repo, path, &params),
+        Method::DELETE => delete(repo, path, &params),
+        _ => error_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed"),
+    }
+}
+
+fn file_entry(path: &str, content: &[u8]) -> Value {
+    json!({
+        "type": "file",
+        "name": path.rsplit('/').next().unwrap_or(path),
+        "path": path,
+        "size": content.len(),
+        "sha": blob_sha(content),
+    })
+}
+
+fn commit_sha(number: u64) -> String {
+    blob_sha(format!("commit {}", number).as_bytes())
+}
+
+/// contents API 的每次写入都是分支上的一个新提交
+fn commit_entry(repo: &mut Repo, message: Option<&String>) -> Value {
+    repo.commits += 1;
+    repo.head = commit_sha(repo.commits);
+
+    json!({
+        "sha": repo.head,
+        "message": message,
+    })
+}
+
+fn get(repo: &Repo, path: &str) -> Response<Body> {
+    if let Some(content) = repo.files.get(path) {
+        let mut file = file_entry(path, content);
+
+        file["encoding"] = json!("base64");
+        file["content"] = json!(base64::encode(content));
+
+        return json_response(StatusCode::OK, file);
+    }
+
+    // 路径是目录时列出其中的文件和子目录
+    let dir = if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", path)
+    };
+
+    let mut dirs = BTreeSet::new();
+    let mut entries = vec![];
+
+    for (file_path, content) in repo.files.range(dir.clone()..) {
+        let rest = match file_path.strip_prefix(dir.as_str()) {
+            Some(rest) => rest,
+            None => break,
+        };
+
+        match rest.split_once('/') {
+            Some((sub_dir, _)) => {
+                dirs.insert(sub_dir.to_string());
+            }
+            None => entries.push(file_entry(file_path, content)),
+        }
+    }
+
+    if entries.is_empty() && dirs.is_empty() {
+        return error_response(StatusCode::NOT_FOUND, "Not Found");
+    }
+
+    entries.extend(dirs.into_iter().map(|name| {
+        json!({
+            "type": "dir",
+            "name": name,
+            "path": format!("{}{}", dir, name),
+        })
+    }));
+
+    json_response(StatusCode::OK, Value::Array(entries))
+}
+
+/// 返回的错误信息作为 400 响应的内容
+fn decode_content(params: &HashMap<String, String>) -> Result<Vec<u8>, String> {
+    let content = params
+        .get("content")
+        .ok_or_else(|| String::from("content is missing"))?;
+
+    base64::decode(content).map_err(|err| format!("content is not base64: {}", err))
+}
+
+/// 文件不存在时返回 404，sha 不是最新版本时返回 409
+fn check_sha(repo: &Repo, path: &str, params: &HashMap<String, String>) -> Option<Response<Body>> {
+    let content = match repo.files.get(path) {
+        Some(content) => content,
+        None => return Some(error_response(StatusCode::NOT_FOUND, "File Not Found")),
+    };
+
+    match params.get("sha") {
+        Some(sha) if *sha == blob_sha(content) => None,
+        Some(_) => Some(error_response(
+            StatusCode::CONFLICT,
+            "sha does not match the latest version of the file",
+        )),
+        None => Some(error_response(StatusCode::BAD_REQUEST, "sha is missing")),
+    }
+}
+
+fn create(repo: &mut Repo, path: &str, params: &HashMap<String, String>) -> Response<Body> {
+    if repo.files.contains_key(path) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "A file with this name already exists",
+        );
+    }
+
+    let content = match decode_content(params) {
+        Ok(content) => content,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let file = file_entry(path, &content);
+    repo.files.insert(path.to_string(), content);
+
+    let commit = commit_entry(repo, params.get("message"));
+
+    json_response(
+        StatusCode::CREATED,
+        json!({ "content": file, "commit": commit }),
+    )
+}
+
+fn update(repo: &mut Repo, path: &str, params: &HashMap<String, String>) -> Response<Body> {
+    if let Some(resp) = check_sha(repo, path, params) {
+        return resp;
+    }
+
+    let content = match decode_content(params) {
+        Ok(content) => content,
+        Err(message) => return error_response(StatusCode::BAD_REQUEST, &message),
+    };
+
+    let file = file_entry(path, &content);
+    repo.files.insert(path.to_string(), content);
+
+    let commit = commit_entry(repo, params.get("message"));
+
+    json_response(StatusCode::OK, json!({ "content": file, "commit": commit }))
+}
+
+fn delete(repo: &mut Repo, path: &str, params: &HashMap<String, String>) -> Response<Body> {
+    if let Some(resp) = check_sha(repo, path, params) {
+        return resp;
+    }
+
+    repo.files.remove(path);
+
+    let commit = commit_entry(repo, params.get("message"));
+
+    json_response(StatusCode::OK, json!({ "content": null, "commit": commit }))
+}
+
+fn git_route(repo: &mut Repo, method: &Method, path: &str, body: &[u8]) -> Response<Body> {
+    let params = match method {
+        &Method::GET => Value::Null,
+        _ => match serde_json::from_slice::<Value>(body) {
+            Ok(params @ Value::Object(_)) => params,
+            _ => return error_response(StatusCode::BAD_REQUEST, "body must be a JSON object"),
+        },
+    };
+
+    match (method, path.split_once('/')) {
+        (&Method::GET, Some(("branches", branch))) if branch == BRANCH => get_branch(repo),
+        (&Method::POST, Some(("git", "blobs"))) => create_blob(repo, &params),
+        (&Method::POST, Some(("git", "trees"))) => create_tree(repo, &params),
+        (&Method::POST, Some(("git", "commits"))) => create_commit(repo, &params),
+        (&Method::PATCH, Some(("git", git_ref))) if git_ref == format!("refs/heads/{}", BRANCH) => {
+            update_ref(repo, &params)
+        }
+        (&Method::GET, Some(("branches", _))) => {
+            error_response(StatusCode::NOT_FOUND, "Branch Not Found")
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+fn get_branch(repo: &Repo) -> Response<Body> {
+    json_response(
+        StatusCode::OK,
+        json!({ "name": BRANCH, "commit": { "sha": repo.head } }),
+    )
+}
+
+/// commit 或 tree 中的全部文件；内容 API 写入的旧提交没有保存，只能找到当前的
+fn snapshot(repo: &Repo, sha: &str) -> Option<BTreeMap<String, Vec<u8>>> {
+    if sha == repo.head {
+        return Some(repo.files.clone());
+    }
+
+    let tree = repo
+        .git_commits
+        .get(sha)
+        .map_or(sha, |commit| commit.tree.as_str());
+
+    repo.trees.get(tree).cloned()
+}
+
+fn create_blob(repo: &mut Repo, params: &Value) -> Response<Body> {
+    let content = match params["content"].as_str() {
+        Some(content) => content,
+        None => return error_response(StatusCode::BAD_REQUEST, "content is missing"),
+    };
+
+    let content = match params["encoding"].as_str() {
+        Some("base64") => match base64::decode(content) {
+            Ok(content) => content,
+            Err(err) => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    &format!("content is not base64: {}", err),
+                )
+            }
+        },
+        _ => content.as_bytes().to_vec(),
+    };
+
+    let sha = blob_sha(&content);
+    repo.blobs.insert(sha.clone(), content);
+
+    json_response(StatusCode::CREATED, json!({ "sha": sha }))
+}
+
+fn create_tree(repo: &mut Repo, params: &Value) -> Response<Body> {
+    let mut files = match params["base_tree"].as_str() {
+        Some(base_tree) => match snapshot(repo, base_tree) {
+            Some(files) => files,
+            None => return error_response(StatusCode::NOT_FOUND, "base_tree Not Found"),
+        },
+        None => BTreeMap::new(),
+    };
+
+    let entries = match params["tree"].as_array() {
+        Some(entries) => entries,
+        None => return error_response(StatusCode::BAD_REQUEST, "tree is missing"),
+    };
+
+    for entry in entries {
+        let (path, sha) = match (entry["path"].as_str(), entry["sha"].as_str()) {
+            (Some(path), Some(sha)) => (path, sha),
+            _ => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    "tree entries need a path and a sha",
+                )
+            }
+        };
+
+        match repo.blobs.get(sha) {
+            Some(content) => files.insert(path.to_string(), content.clone()),
+            None => return error_response(StatusCode::NOT_FOUND, "Blob Not Found"),
+        };
+    }
+
+    let sha = blob_sha(format!("tree {}", repo.trees.len() + 1).as_bytes());
+    repo.trees.insert(sha.clone(), files);
+
+    json_response(StatusCode::CREATED, json!({ "sha": sha }))
+}
+
+fn create_commit(repo: &mut Repo, params: &Value) -> Response<Body> {
+    let tree = match params["tree"].as_str() {
+        Some(tree) if repo.trees.contains_key(tree) => tree.to_string(),
+        Some(_) => return error_response(StatusCode::NOT_FOUND, "Tree Not Found"),
+        None => return error_response(StatusCode::BAD_REQUEST, "tree is missing"),
+    };
+
+    let parents: Vec<String> = params["parents"]
+        .as_array()
+        .map(|parents| {
+            parents
+                .iter()
+                .filter_map(|parent| Some(parent.as_str()?.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // 新的 commit 在更新 ref 之前不在分支上
+    repo.commits += 1;
+    let sha = commit_sha(repo.commits);
+
+    repo.git_commits.insert(
+        sha.clone(),
+        GitCommit {
+            tree: tree.clone(),
+            parents,
+        },
+    );
+
+    json_response(
+        StatusCode::CREATED,
+        json!({ "sha": sha, "tree": { "sha": tree }, "message": params["message"] }),
+    )
+}
+
+fn update_ref(repo: &mut Repo, params: &Value) -> Response<Body> {
+    let sha = match params["sha"].as_str() {
+        Some(sha) => sha.to_string(),
+        None => return error_response(StatusCode::BAD_REQUEST, "sha is missing"),
+    };
+
+    let commit = match repo.git_commits.get(&sha) {
+        Some(commit) => commit,
+        None => return error_response(StatusCode::NOT_FOUND, "Commit Not Found"),
+    };
+
+    // 不强制更新时只能快进：新 commit 必须基于当前的 head
+    if !params["force"].as_bool().unwrap_or(false) && !commit.parents.contains(&repo.head) {
+        return error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Update is not a fast forward",
+        );
+    }
+
+    repo.files = repo.trees[&commit.tree].clone();
+    repo.head = sha.clone();
+
+    json_response(
+        StatusCode::OK,
+        json!({
+            "ref": format!("refs/heads/{}", BRANCH),
+            "object": { "type": "commit", "sha": sha },
+        }),
+    )
+}
@@ -1,8 +1,11 @@
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
-    }
-}
+//! Test support for the Bapao crates.
+//!
+//! `MockGitee` runs a local HTTP server emulating the part of the Gitee
+//! contents API and Git Data API the transport uses, so the request loop (`BtpClient` →
+//! mailbox → `AppListener` → mailbox → `BtpClient`) can be exercised in
+//! `cargo test` without a real repository. Failures and rate limiting can be
+//! injected to test how the loop copes with them.
+
+mod gitee;
+
+pub use gitee::{Fault, MockGitee, RecordedRequest, BRANCH, MAILBOX, OWNER, REPO, TOKEN};